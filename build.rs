@@ -1,6 +1,6 @@
 extern crate cbindgen;
 
-use std::env;
+use std::{env, fs, path::Path};
 use version_number::FullVersion;
 
 fn main() {
@@ -15,9 +15,19 @@ fn main() {
 
     println!("cargo:rustc-env=XSYNTHVERSION={}", ver);
 
-    // CBINDGEN
-
     let crate_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let lockfile = fs::read_to_string(Path::new(&crate_dir).join("Cargo.lock")).unwrap_or_default();
+
+    println!(
+        "cargo:rustc-env=XSYNTHCOREVERSION={}",
+        locked_version(&lockfile, "xsynth-core")
+    );
+    println!(
+        "cargo:rustc-env=XSYNTHREALTIMEVERSION={}",
+        locked_version(&lockfile, "xsynth-realtime")
+    );
+
+    // CBINDGEN
 
     let mut config = cbindgen::Config::from_file("cbindgen.toml")
         .expect("Unable to find cbindgen.toml configuration file");
@@ -32,3 +42,17 @@ fn main() {
         .expect("Unable to generate bindings")
         .write_to_file("xsynth.h");
 }
+
+/// Finds the version of a dependency in the contents of Cargo.lock.
+fn locked_version(lockfile: &str, package: &str) -> String {
+    let name = format!("name = \"{}\"", package);
+    let mut lines = lockfile.lines();
+    while let Some(line) = lines.next() {
+        if line == name {
+            if let Some(version) = lines.next().and_then(|l| l.strip_prefix("version = ")) {
+                return version.trim_matches('"').to_string();
+            }
+        }
+    }
+    "unknown".to_string()
+}
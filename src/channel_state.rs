@@ -0,0 +1,95 @@
+use xsynth_core::channel::{ChannelAudioEvent, ControlEvent};
+
+/// Controllers that select and write to registered/non-registered
+/// parameters. They are replayed last, so that the selected parameter
+/// receives its data entry value.
+const PARAMETER_CONTROLLERS: [u8; 6] = [99, 98, 101, 100, 6, 38];
+
+/// The controller state of a single MIDI channel, as tracked from the events
+/// sent through the C API.
+#[derive(Debug, Clone)]
+pub(crate) struct ChannelState {
+    pub program: u8,
+    pub controls: [Option<u8>; 128],
+    pub pitch_bend: f32,
+    /// The data entry values (coarse, fine) written to the pitch bend
+    /// sensitivity RPN, which are lost from the controllers once another
+    /// parameter is selected.
+    pub pitch_bend_range: Option<(u8, Option<u8>)>,
+}
+
+impl ChannelState {
+    pub(crate) fn new() -> Self {
+        Self {
+            program: 0,
+            controls: [None; 128],
+            pitch_bend: 0.0,
+            pitch_bend_range: None,
+        }
+    }
+
+    pub(crate) fn update(&mut self, event: &ChannelAudioEvent) {
+        match event {
+            ChannelAudioEvent::ProgramChange(program) => self.program = *program,
+            ChannelAudioEvent::ResetControl => {
+                self.controls = [None; 128];
+                self.pitch_bend = 0.0;
+                self.pitch_bend_range = None;
+            }
+            ChannelAudioEvent::Control(ControlEvent::Raw(controller, value)) => {
+                if let Some(control) = self.controls.get_mut(*controller as usize) {
+                    *control = Some(*value);
+                }
+                if self.controls[101] == Some(0) && self.controls[100] == Some(0) {
+                    match controller {
+                        6 => self.pitch_bend_range = Some((*value, None)),
+                        38 => {
+                            let coarse = self.pitch_bend_range.map_or(2, |(coarse, _)| coarse);
+                            self.pitch_bend_range = Some((coarse, Some(*value)));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            ChannelAudioEvent::Control(ControlEvent::PitchBendValue(value)) => {
+                self.pitch_bend = *value;
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the events needed to bring a channel to this state.
+    pub(crate) fn to_events(&self) -> Vec<ChannelAudioEvent> {
+        let mut events = vec![
+            ChannelAudioEvent::ResetControl,
+            ChannelAudioEvent::ProgramChange(self.program),
+        ];
+
+        let raw =
+            |controller, value| ChannelAudioEvent::Control(ControlEvent::Raw(controller, value));
+        for controller in (0..120u8).filter(|c| !PARAMETER_CONTROLLERS.contains(c)) {
+            if let Some(value) = self.controls[controller as usize] {
+                events.push(raw(controller, value));
+            }
+        }
+
+        // The pitch bend range is written first, as the parameter selected
+        // last may be a different one.
+        if let Some((coarse, fine)) = self.pitch_bend_range {
+            events.extend([raw(101, 0), raw(100, 0), raw(6, coarse)]);
+            if let Some(fine) = fine {
+                events.push(raw(38, fine));
+            }
+        }
+        for controller in PARAMETER_CONTROLLERS {
+            if let Some(value) = self.controls[controller as usize] {
+                events.push(raw(controller, value));
+            }
+        }
+
+        events.push(ChannelAudioEvent::Control(ControlEvent::PitchBendValue(
+            self.pitch_bend,
+        )));
+        events
+    }
+}
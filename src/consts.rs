@@ -12,5 +12,31 @@ pub const MIDI_EVENT_COARSETUNE: u16 = 9;
 pub const INTERPOLATION_NEAREST: u16 = 100;
 pub const INTERPOLATION_LINEAR: u16 = 101;
 
+/// The INTERPOLATION_* constants supported by this build. Nearest neighbor
+/// interpolation is always available, the others depend on crate features.
+#[cfg(feature = "interpolation-linear")]
+pub(crate) const SUPPORTED_INTERPOLATORS: &[u16] = &[INTERPOLATION_NEAREST, INTERPOLATION_LINEAR];
+#[cfg(not(feature = "interpolation-linear"))]
+pub(crate) const SUPPORTED_INTERPOLATORS: &[u16] = &[INTERPOLATION_NEAREST];
+
 pub const AUDIO_CHANNELS_MONO: u16 = 1;
 pub const AUDIO_CHANNELS_STEREO: u16 = 2;
+
+pub const SAMPLE_FORMAT_DEFAULT: u16 = 200;
+pub const SAMPLE_FORMAT_F32: u16 = 201;
+pub const SAMPLE_FORMAT_I16: u16 = 202;
+pub const SAMPLE_FORMAT_U16: u16 = 203;
+
+pub const SYNTH_KIND_CHANNEL_GROUP: u16 = 300;
+pub const SYNTH_KIND_REALTIME: u16 = 301;
+
+pub const OUTPUT_CLAMP_NONE: u16 = 400;
+pub const OUTPUT_CLAMP_HARD: u16 = 401;
+pub const OUTPUT_CLAMP_TANH: u16 = 402;
+
+pub const RESAMPLER_LINEAR: u16 = 500;
+pub const RESAMPLER_SINC: u16 = 501;
+
+pub const RESULT_OK: i32 = 0;
+pub const RESULT_ERROR_SOUNDFONT_LOAD: i32 = 1;
+pub const RESULT_ERROR_PANIC: i32 = 2;
@@ -0,0 +1,397 @@
+use crate::consts::*;
+
+/// Width of the soft knee of the compressor, in dB.
+const COMPRESSOR_KNEE_DB: f32 = 6.0;
+
+/// How fast the held peak levels fall between reads, in dB per second.
+const PEAK_FALL_DB_PER_SEC: f32 = 20.0;
+
+/// Cutoff frequency of the DC offset filter, in Hz.
+const DC_FILTER_CUTOFF_HZ: f32 = 5.0;
+
+/// Returns the smoothing coefficient of a one-pole filter reaching its target
+/// in approximately the given time.
+pub(crate) fn time_coefficient(time_ms: f32, sample_rate: u32) -> f32 {
+    if time_ms <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / (time_ms * 0.001 * sample_rate as f32)).exp()
+    }
+}
+
+/// A feed-forward compressor with a soft knee, linked across all channels.
+#[derive(Clone)]
+pub(crate) struct Compressor {
+    pub threshold: f32,
+    pub ratio: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    reduction: f32,
+}
+
+impl Compressor {
+    pub(crate) fn new(threshold: f32, ratio: f32, attack_ms: f32, release_ms: f32) -> Self {
+        Self {
+            threshold,
+            ratio: ratio.max(1.0),
+            attack_ms,
+            release_ms,
+            reduction: 0.0,
+        }
+    }
+
+    /// Returns the gain reduction in dB for the given input level in dB.
+    fn gain_reduction(&self, level: f32) -> f32 {
+        let over = level - self.threshold;
+        let slope = 1.0 - 1.0 / self.ratio;
+
+        if 2.0 * over <= -COMPRESSOR_KNEE_DB {
+            0.0
+        } else if 2.0 * over.abs() <= COMPRESSOR_KNEE_DB {
+            let x = over + COMPRESSOR_KNEE_DB / 2.0;
+            slope * x * x / (2.0 * COMPRESSOR_KNEE_DB)
+        } else {
+            slope * over
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: u16, sample_rate: u32) {
+        let attack = time_coefficient(self.attack_ms, sample_rate);
+        let release = time_coefficient(self.release_ms, sample_rate);
+
+        for frame in buffer.chunks_exact_mut(channels as usize) {
+            let peak = frame.iter().fold(0.0f32, |acc, s| acc.max(s.abs()));
+            let level = 20.0 * peak.max(1e-9).log10();
+
+            let target = self.gain_reduction(level);
+            let coeff = if target > self.reduction {
+                attack
+            } else {
+                release
+            };
+            self.reduction = target + coeff * (self.reduction - target);
+
+            let gain = 10f32.powf(-self.reduction / 20.0);
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+        }
+    }
+}
+
+/// Time over which a change of a SmoothedGain is applied.
+pub(crate) const GAIN_SMOOTHING_MS: f32 = 30.0;
+
+/// A gain that moves towards its target over about 30ms instead of jumping,
+/// so that it can follow a fader without clicks.
+#[derive(Clone)]
+pub(crate) struct SmoothedGain {
+    pub target: f32,
+    current: f32,
+}
+
+impl SmoothedGain {
+    pub(crate) fn new(gain: f32) -> Self {
+        Self {
+            target: gain,
+            current: gain,
+        }
+    }
+
+    pub(crate) fn process(&mut self, buffer: &mut [f32], channels: u16, sample_rate: u32) {
+        if self.current == self.target {
+            if self.target != 1.0 {
+                buffer.iter_mut().for_each(|s| *s *= self.target);
+            }
+            return;
+        }
+
+        let coefficient = time_coefficient(GAIN_SMOOTHING_MS, sample_rate);
+        for frame in buffer.chunks_exact_mut(channels as usize) {
+            self.current = self.target + coefficient * (self.current - self.target);
+            if (self.current - self.target).abs() < 1e-5 {
+                self.current = self.target;
+            }
+
+            for sample in frame.iter_mut() {
+                *sample *= self.current;
+            }
+        }
+    }
+}
+
+/// A one-pole high-pass filter removing DC offset from the first two audio
+/// channels.
+#[derive(Clone)]
+pub(crate) struct DcFilter {
+    prev_input: [f32; 2],
+    prev_output: [f32; 2],
+}
+
+impl DcFilter {
+    pub(crate) fn new() -> Self {
+        Self {
+            prev_input: [0.0; 2],
+            prev_output: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: u16, sample_rate: u32) {
+        let pole = (-2.0 * std::f32::consts::PI * DC_FILTER_CUTOFF_HZ / sample_rate as f32).exp();
+
+        for frame in buffer.chunks_exact_mut(channels as usize) {
+            for (i, sample) in frame.iter_mut().take(2).enumerate() {
+                let output = *sample - self.prev_input[i] + pole * self.prev_output[i];
+                self.prev_input[i] = *sample;
+                self.prev_output[i] = output;
+                *sample = output;
+            }
+        }
+    }
+}
+
+/// A linear fade to silence over a fixed number of frames, applied once.
+#[derive(Clone)]
+pub(crate) struct FadeOut {
+    length: u64,
+    position: u64,
+}
+
+impl FadeOut {
+    pub(crate) fn new(length: u64) -> Self {
+        Self {
+            length,
+            position: 0,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.position >= self.length
+    }
+
+    fn process(&mut self, buffer: &mut [f32], channels: u16) {
+        for frame in buffer.chunks_exact_mut(channels as usize) {
+            if self.is_done() {
+                break;
+            }
+            let gain = 1.0 - (self.position + 1) as f32 / self.length as f32;
+            for sample in frame.iter_mut() {
+                *sample *= gain;
+            }
+            self.position += 1;
+        }
+    }
+}
+
+/// Tracks the peak level of the first two audio channels between reads.
+#[derive(Clone)]
+pub(crate) struct PeakMeter {
+    current: [f32; 2],
+    held: [f32; 2],
+    frames: u64,
+}
+
+impl PeakMeter {
+    fn new() -> Self {
+        Self {
+            current: [0.0; 2],
+            held: [0.0; 2],
+            frames: 0,
+        }
+    }
+
+    fn update(&mut self, buffer: &[f32], channels: u16) {
+        for frame in buffer.chunks_exact(channels as usize) {
+            for (peak, sample) in self.current.iter_mut().zip(frame) {
+                *peak = peak.max(sample.abs());
+            }
+        }
+        self.frames += (buffer.len() / channels as usize) as u64;
+    }
+
+    /// Returns the peak levels since the last call. The previously returned
+    /// levels fall off over time, so a short peak stays visible for a while.
+    pub(crate) fn take(&mut self, sample_rate: u32) -> [f32; 2] {
+        let elapsed = self.frames as f32 / sample_rate as f32;
+        let decay = 10f32.powf(-PEAK_FALL_DB_PER_SEC * elapsed / 20.0);
+
+        for (held, current) in self.held.iter_mut().zip(self.current.iter_mut()) {
+            *held = current.max(*held * decay);
+            *current = 0.0;
+        }
+        self.frames = 0;
+
+        self.held
+    }
+}
+
+/// Tracks the correlation between the left and right channels of stereo
+/// audio between reads.
+#[derive(Clone)]
+pub(crate) struct CorrelationMeter {
+    sum_lr: f64,
+    sum_ll: f64,
+    sum_rr: f64,
+}
+
+impl CorrelationMeter {
+    fn new() -> Self {
+        Self {
+            sum_lr: 0.0,
+            sum_ll: 0.0,
+            sum_rr: 0.0,
+        }
+    }
+
+    fn update(&mut self, buffer: &[f32]) {
+        for frame in buffer.chunks_exact(2) {
+            let (l, r) = (frame[0] as f64, frame[1] as f64);
+            self.sum_lr += l * r;
+            self.sum_ll += l * l;
+            self.sum_rr += r * r;
+        }
+    }
+
+    /// Returns the correlation since the last call, from -1 (opposite phase)
+    /// to 1 (identical channels). Silence is reported as 0.
+    pub(crate) fn take(&mut self) -> f32 {
+        let energy = (self.sum_ll * self.sum_rr).sqrt();
+        let correlation = if energy > 0.0 {
+            self.sum_lr / energy
+        } else {
+            0.0
+        };
+        *self = Self::new();
+        correlation as f32
+    }
+}
+
+/// Processing applied to the mixed output of a channel group, after it is
+/// rendered by XSynth.
+#[derive(Clone)]
+pub(crate) struct MasterEffects {
+    pub dc_filter: Option<DcFilter>,
+    pub stereo_width: f32,
+    pub compressor: Option<Compressor>,
+    pub swap_channels: bool,
+    pub fade_out: Option<FadeOut>,
+    /// One of the OUTPUT_CLAMP_* constants.
+    pub output_clamp: u16,
+    pub meter: PeakMeter,
+    pub correlation: CorrelationMeter,
+}
+
+impl MasterEffects {
+    pub(crate) fn new() -> Self {
+        Self {
+            dc_filter: None,
+            stereo_width: 1.0,
+            compressor: None,
+            swap_channels: false,
+            fade_out: None,
+            output_clamp: OUTPUT_CLAMP_NONE,
+            meter: PeakMeter::new(),
+            correlation: CorrelationMeter::new(),
+        }
+    }
+
+    pub(crate) fn process(&mut self, buffer: &mut [f32], channels: u16, sample_rate: u32) {
+        if let Some(filter) = &mut self.dc_filter {
+            filter.process(buffer, channels, sample_rate);
+        }
+
+        if channels == 2 && self.stereo_width != 1.0 {
+            apply_stereo_width(buffer, self.stereo_width);
+        }
+
+        if let Some(compressor) = &mut self.compressor {
+            compressor.process(buffer, channels, sample_rate);
+        }
+
+        if channels == 2 && self.swap_channels {
+            for frame in buffer.chunks_exact_mut(2) {
+                frame.swap(0, 1);
+            }
+        }
+
+        if let Some(fade) = &mut self.fade_out {
+            fade.process(buffer, channels);
+            if fade.is_done() {
+                self.fade_out = None;
+            }
+        }
+
+        self.meter.update(buffer, channels);
+        if channels == 2 {
+            self.correlation.update(buffer);
+        }
+
+        // Clamped after metering, so that the meters still show overs.
+        match self.output_clamp {
+            OUTPUT_CLAMP_HARD => buffer.iter_mut().for_each(|s| *s = s.clamp(-1.0, 1.0)),
+            OUTPUT_CLAMP_TANH => buffer.iter_mut().for_each(|s| *s = s.tanh()),
+            _ => {}
+        }
+    }
+}
+
+fn apply_stereo_width(buffer: &mut [f32], width: f32) {
+    for frame in buffer.chunks_exact_mut(2) {
+        let mid = (frame[0] + frame[1]) * 0.5;
+        let side = (frame[0] - frame[1]) * 0.5 * width;
+        frame[0] = mid + side;
+        frame[1] = mid - side;
+    }
+}
+
+/// Number of points the true peak is estimated at between two samples.
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+
+/// Level statistics of a block of audio, all as linear amplitudes.
+pub(crate) struct AudioLevels {
+    pub peak: f32,
+    pub rms: f32,
+    pub true_peak: f32,
+}
+
+/// Measures the sample peak, RMS and estimated true peak of a block of
+/// audio. The true peak is estimated by oversampling each channel with cubic
+/// interpolation, which finds most inter-sample peaks.
+pub(crate) fn analyze_levels(buffer: &[f32], channels: u16) -> AudioLevels {
+    let channels = channels as usize;
+    let frames = buffer.len() / channels;
+    let buffer = &buffer[..frames * channels];
+
+    let peak = buffer.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    let sum: f64 = buffer.iter().map(|&s| s as f64 * s as f64).sum();
+    let rms = if buffer.is_empty() {
+        0.0
+    } else {
+        (sum / buffer.len() as f64).sqrt() as f32
+    };
+
+    let mut true_peak = peak;
+    for channel in 0..channels {
+        let sample =
+            |i: isize| buffer[i.clamp(0, frames as isize - 1) as usize * channels + channel];
+        for i in 0..frames.saturating_sub(1) as isize {
+            let (p0, p1, p2, p3) = (sample(i - 1), sample(i), sample(i + 1), sample(i + 2));
+            for step in 1..TRUE_PEAK_OVERSAMPLING {
+                let t = step as f32 / TRUE_PEAK_OVERSAMPLING as f32;
+                // Catmull-Rom spline through the four samples
+                let value = 0.5
+                    * (2.0 * p1
+                        + (p2 - p0) * t
+                        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t
+                        + (3.0 * (p1 - p2) + p3 - p0) * t * t * t);
+                true_peak = true_peak.max(value.abs());
+            }
+        }
+    }
+
+    AudioLevels {
+        peak,
+        rms,
+        true_peak,
+    }
+}
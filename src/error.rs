@@ -0,0 +1,59 @@
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn set_last_error(message: &str) {
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Runs the body of an exported function, making sure no panic unwinds into
+/// the calling code. If the body panics, the panic message is stored as the
+/// last error and the given fallback value is returned instead.
+pub(crate) fn catch_panic<T>(fallback: T, f: impl FnOnce() -> T) -> T {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => value,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Unknown error".to_string());
+            set_last_error(&message);
+            fallback
+        }
+    }
+}
+
+/// Returns a description of the last error that occurred in an XSynth
+/// function called from the current thread.
+///
+/// When a function fails, it returns a null handle, zero, or an error code
+/// (depending on its return type), and the reason is stored so that it can be
+/// retrieved using this function.
+///
+/// --Returns--
+/// A null-terminated string describing the error, or null if no error has
+/// occurred. The string is valid until the next error occurs on the same
+/// thread, and must not be freed.
+#[no_mangle]
+pub extern "C" fn XSynth_GetLastError() -> *const c_char {
+    LAST_ERROR.with(|e| match &*e.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Clears the last error of the current thread, so that
+/// XSynth_GetLastError returns null until a new error occurs.
+#[no_mangle]
+pub extern "C" fn XSynth_ClearLastError() {
+    LAST_ERROR.with(|e| *e.borrow_mut() = None);
+}
@@ -1,9 +1,708 @@
-use std::{ffi::c_void, sync::Arc};
+use crate::{
+    channel_state::ChannelState,
+    convert_streamparams_to_c, convert_streamparams_to_rust,
+    effects::{MasterEffects, SmoothedGain},
+    midi::MidiParser,
+    overload::{OverloadMonitor, DEFAULT_OVERLOAD_THRESHOLD},
+    registry::{GROUPS, REALTIME_SYNTHS, SOUNDFONTS},
+    resample::{Resampler, ResamplerQuality},
+    ring::RingBuffer,
+    soundfont::{load_soundfont, load_wav_instrument, XSynth_SoundfontOptions},
+    vibrato::{Vibrato, VIBRATO_STEP_FRAMES},
+    voices::{GainSoundfont, GainSpawner, LimitedSoundfont, VoiceLimit},
+    XSynth_StreamParams,
+};
+use cpal::{Device, SupportedStreamConfig};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::{c_void, CString},
+    ops::RangeInclusive,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex, RwLock, RwLockReadGuard, Weak,
+    },
+};
 use xsynth_core::{
-    channel_group::ChannelGroup,
-    soundfont::{SampleSoundfont, SoundfontBase},
+    channel::{ChannelAudioEvent, ChannelConfigEvent, ChannelInitOptions, ControlEvent},
+    channel_group::{ChannelGroup, ChannelGroupConfig, SynthEvent},
+    soundfont::{SampleSoundfont, SoundfontBase, VoiceSpawner},
+    AudioPipe, AudioStreamParams,
 };
 pub use xsynth_realtime::RealtimeSynth;
+use xsynth_realtime::XSynthRealtimeConfig;
+
+fn to_dyn_soundfonts(soundfonts: &[Arc<SoundfontInstance>]) -> Vec<Arc<dyn SoundfontBase>> {
+    soundfonts
+        .iter()
+        .map(|sf| sf.clone() as Arc<dyn SoundfontBase>)
+        .collect()
+}
+
+/// The settings a channel group was created with, kept so that the group
+/// can be rebuilt with different audio parameters.
+#[derive(Clone)]
+pub(crate) struct GroupSettings {
+    pub channel_count: u32,
+    pub drums_channels: Vec<u32>,
+    pub use_threadpool: bool,
+    pub fade_out_killing: bool,
+}
+
+impl GroupSettings {
+    pub(crate) fn build(&self, audio_params: AudioStreamParams) -> ChannelGroup {
+        let channel_init_options = ChannelInitOptions {
+            fade_out_killing: self.fade_out_killing,
+            drums_only: false,
+        };
+
+        let config = ChannelGroupConfig {
+            channel_init_options,
+            channel_count: self.channel_count,
+            drums_channels: self.drums_channels.clone(),
+            audio_params,
+            use_threadpool: self.use_threadpool,
+        };
+
+        ChannelGroup::new(config)
+    }
+}
+
+/// Snaps a key to the nearest key of the given scale, preferring the lower
+/// one if two are equally close.
+fn quantize_key(key: i32, root: u8, scale: &[bool; 12]) -> i32 {
+    let in_scale =
+        |k: i32| (0..128).contains(&k) && scale[(k - root as i32).rem_euclid(12) as usize];
+    (0..12)
+        .flat_map(|d| [key - d, key + d])
+        .find(|&k| in_scale(k))
+        .unwrap_or(key)
+}
+
+/// The settings a realtime synthesizer was created with, kept so that it can
+/// be rebuilt with a different configuration.
+#[derive(Clone)]
+pub(crate) struct RealtimeSettings {
+    pub group: GroupSettings,
+    pub render_window_ms: f64,
+    pub ignore_range: RangeInclusive<u8>,
+}
+
+impl RealtimeSettings {
+    pub(crate) fn realtime_config(&self) -> XSynthRealtimeConfig {
+        XSynthRealtimeConfig {
+            channel_init_options: ChannelInitOptions {
+                fade_out_killing: self.group.fade_out_killing,
+                drums_only: false,
+            },
+            render_window_ms: self.render_window_ms,
+            channel_count: self.group.channel_count,
+            drums_channels: self.group.drums_channels.clone(),
+            use_threadpool: self.group.use_threadpool,
+            ignore_range: self.ignore_range.clone(),
+        }
+    }
+}
+
+/// A note that is held on a channel, after the global transpose is applied.
+#[derive(Clone, Copy)]
+struct PlayedNote {
+    key: u8,
+    /// The frame the note started at.
+    start: u64,
+}
+
+/// Internal state of a channel group, owned by its handle.
+pub(crate) struct GroupInstance {
+    pub group: ChannelGroup,
+    pub settings: GroupSettings,
+    pub soundfonts: Vec<Arc<SoundfontInstance>>,
+    /// The soundfonts as sent to the channel group, loaded with its audio
+    /// parameters.
+    soundfont_views: Vec<Arc<dyn SoundfontBase>>,
+    pub layers: Option<usize>,
+    pub effects: MasterEffects,
+    pub choke_groups: HashMap<(u32, u8), u32>,
+    pub velocity_curve: Option<[u8; 128]>,
+    pub key_ranges: HashMap<u32, RangeInclusive<u8>>,
+    pub channel_states: Vec<ChannelState>,
+    pub ring: Option<Arc<RingBuffer>>,
+    vibrato: Vibrato,
+    pub muted_channels: HashSet<u32>,
+    pub disabled_channels: HashSet<u32>,
+    pub note_limits: HashMap<u32, u32>,
+    pub voice_limit: Arc<VoiceLimit>,
+    pub active_keys: Vec<[bool; 128]>,
+    pub transpose: i32,
+    /// The root key and the keys of the scale notes are snapped to, relative
+    /// to the root.
+    pub scale_quantize: Option<(u8, [bool; 12])>,
+    pub max_note_duration: f64,
+    /// Number of audio frames rendered so far.
+    pub clock: u64,
+    played_keys: Vec<[Option<PlayedNote>; 128]>,
+    scratch: Vec<f32>,
+    /// The audio parameters of the output. The channel group renders at a
+    /// multiple of this sample rate when oversampling is enabled.
+    params: AudioStreamParams,
+    pub oversampling: u16,
+    /// Filters the oversampled audio down to the output sample rate.
+    decimator: Option<Resampler>,
+    oversampled: Vec<f32>,
+    /// Output samples that were filtered ahead of the last read.
+    decimated: Vec<f32>,
+}
+
+impl GroupInstance {
+    pub(crate) fn new(settings: GroupSettings, audio_params: AudioStreamParams) -> Self {
+        let channel_count = settings.channel_count as usize;
+        let voice_limit = VoiceLimit::new(settings.fade_out_killing);
+        Self {
+            group: settings.build(audio_params),
+            channel_states: vec![ChannelState::new(); channel_count],
+            settings,
+            soundfonts: Vec::new(),
+            soundfont_views: Vec::new(),
+            layers: None,
+            effects: MasterEffects::new(),
+            choke_groups: HashMap::new(),
+            velocity_curve: None,
+            key_ranges: HashMap::new(),
+            ring: None,
+            vibrato: Vibrato::new(channel_count),
+            muted_channels: HashSet::new(),
+            disabled_channels: HashSet::new(),
+            note_limits: HashMap::new(),
+            voice_limit,
+            active_keys: vec![[false; 128]; channel_count],
+            transpose: 0,
+            scale_quantize: None,
+            max_note_duration: 0.0,
+            clock: 0,
+            played_keys: vec![[None; 128]; channel_count],
+            scratch: Vec::new(),
+            params: audio_params,
+            oversampling: 1,
+            decimator: None,
+            oversampled: Vec::new(),
+            decimated: Vec::new(),
+        }
+    }
+
+    /// Returns the audio parameters of the output of the group.
+    pub(crate) fn stream_params(&self) -> AudioStreamParams {
+        self.params
+    }
+
+    /// Builds a new channel group rendering at the given output parameters
+    /// and oversampling factor. The soundfonts and controller state are not
+    /// sent to it.
+    fn build_group(&mut self, params: AudioStreamParams, oversampling: u16) {
+        let internal =
+            AudioStreamParams::new(params.sample_rate * oversampling as u32, params.channels);
+        self.group = self.settings.build(internal);
+        self.params = params;
+        self.oversampling = oversampling;
+        self.decimator = (oversampling > 1).then(|| {
+            Resampler::new(
+                ResamplerQuality::Sinc,
+                internal.sample_rate,
+                params.sample_rate,
+                params.channels.count() as usize,
+            )
+        });
+        self.decimated.clear();
+    }
+
+    /// Creates a new group with the same settings, soundfonts and controller
+    /// state, but without any active voices.
+    pub(crate) fn duplicate(&self) -> Self {
+        let mut new = Self::new(self.settings.clone(), self.params);
+        if self.oversampling > 1 {
+            new.build_group(self.params, self.oversampling);
+        }
+        new.effects = self.effects.clone();
+        new.choke_groups = self.choke_groups.clone();
+        new.velocity_curve = self.velocity_curve;
+        new.key_ranges = self.key_ranges.clone();
+        new.muted_channels = self.muted_channels.clone();
+        new.disabled_channels = self.disabled_channels.clone();
+        new.note_limits = self.note_limits.clone();
+        new.voice_limit.set_max(self.voice_limit.max());
+        new.transpose = self.transpose;
+        new.scale_quantize = self.scale_quantize;
+        new.max_note_duration = self.max_note_duration;
+        new.soundfonts = self.soundfonts.clone();
+        new.soundfont_views = self.soundfont_views.clone();
+        new.send_soundfonts();
+        new.set_layer_count(self.layers);
+        new.restore_channel_states(&self.channel_states);
+        new.set_master_tuning(self.vibrato.master_tuning());
+        new
+    }
+
+    /// Shifts the pitch of all channels by the given amount of cents, on top
+    /// of their own fine tuning.
+    pub(crate) fn set_master_tuning(&mut self, cents: f32) {
+        for (channel, cents) in self.vibrato.set_master_tuning(cents) {
+            self.group.send_event(SynthEvent::Channel(
+                channel,
+                ChannelAudioEvent::Control(ControlEvent::FineTune(cents)),
+            ));
+        }
+    }
+
+    pub(crate) fn send_event(&mut self, mut event: SynthEvent) {
+        if let SynthEvent::Channel(channel, _) = &event {
+            if self.disabled_channels.contains(channel) {
+                return;
+            }
+        }
+        let mut note_on = None;
+        if let SynthEvent::Channel(channel, ChannelAudioEvent::NoteOn { key, vel }) = &mut event {
+            if self.muted_channels.contains(channel) {
+                return;
+            }
+            if let Some(range) = self.key_ranges.get(channel) {
+                if !range.contains(key) {
+                    return;
+                }
+            }
+            if *vel > 0 {
+                if let Some(curve) = &self.velocity_curve {
+                    *vel = curve[*vel as usize & 127].min(127);
+                    // A curve mapping the velocity to 0 silences the note,
+                    // rather than turning it into a note off.
+                    if *vel == 0 {
+                        return;
+                    }
+                }
+                note_on = Some((*channel, *key));
+            }
+        }
+        if let SynthEvent::Channel(channel, ev) = &mut event {
+            if !self.transpose_note(*channel, ev) {
+                return;
+            }
+        }
+        // Only notes that are actually played choke other notes and count
+        // against the note limit.
+        if let Some((channel, key)) = note_on {
+            self.choke(channel, key);
+            self.enforce_note_limit(channel, key);
+        }
+        if let SynthEvent::Channel(channel, ev) = &mut event {
+            if let Some(state) = self.channel_states.get_mut(*channel as usize) {
+                state.update(ev);
+            }
+            if let ChannelAudioEvent::Control(ControlEvent::FineTune(cents)) = ev {
+                *cents = self.vibrato.set_fine_tune(*channel, *cents);
+            }
+            self.track_keys(*channel, ev);
+        }
+        self.group.send_event(event);
+    }
+
+    /// Applies the global transpose to a note event. The key and start time of
+    /// each note are remembered, so it is released correctly even if the
+    /// transpose changes while it is held. Returns false if the transposed
+    /// note is out of range and should be dropped.
+    fn transpose_note(&mut self, channel: u32, event: &mut ChannelAudioEvent) -> bool {
+        let Some(played) = self.played_keys.get_mut(channel as usize) else {
+            return true;
+        };
+
+        match event {
+            ChannelAudioEvent::NoteOn { key, vel } if *vel > 0 => {
+                let is_drums = self.settings.drums_channels.contains(&channel);
+                let mut shifted = *key as i32 + if is_drums { 0 } else { self.transpose };
+                if let (Some((root, scale)), false) = (&self.scale_quantize, is_drums) {
+                    shifted = quantize_key(shifted, *root, scale);
+                }
+                if !(0..128).contains(&shifted) {
+                    played[*key as usize & 127] = None;
+                    return false;
+                }
+                played[*key as usize & 127] = Some(PlayedNote {
+                    key: shifted as u8,
+                    start: self.clock,
+                });
+                *key = shifted as u8;
+            }
+            ChannelAudioEvent::NoteOn { key, .. } | ChannelAudioEvent::NoteOff { key } => {
+                if let Some(note) = played[*key as usize & 127].take() {
+                    *key = note.key;
+                }
+            }
+            ChannelAudioEvent::AllNotesOff | ChannelAudioEvent::AllNotesKilled => {
+                *played = [None; 128];
+            }
+            _ => {}
+        }
+        true
+    }
+
+    /// Keeps track of the keys that are held on each channel.
+    fn track_keys(&mut self, channel: u32, event: &ChannelAudioEvent) {
+        let Some(keys) = self.active_keys.get_mut(channel as usize) else {
+            return;
+        };
+
+        match event {
+            ChannelAudioEvent::NoteOn { key, vel } => keys[*key as usize & 127] = *vel > 0,
+            ChannelAudioEvent::NoteOff { key } => keys[*key as usize & 127] = false,
+            ChannelAudioEvent::AllNotesOff | ChannelAudioEvent::AllNotesKilled => {
+                *keys = [false; 128]
+            }
+            _ => {}
+        }
+    }
+
+    /// Stops all notes and resets the controllers and program of all channels.
+    pub(crate) fn reset(&mut self) {
+        for channel in 0..self.settings.channel_count {
+            self.send_event(SynthEvent::Channel(
+                channel,
+                ChannelAudioEvent::AllNotesKilled,
+            ));
+            self.send_event(SynthEvent::Channel(
+                channel,
+                ChannelAudioEvent::ResetControl,
+            ));
+            self.send_event(SynthEvent::Channel(
+                channel,
+                ChannelAudioEvent::ProgramChange(0),
+            ));
+        }
+    }
+
+    /// Brings all channels to the given controller state. The events are
+    /// sent like any other, so disabled channels ignore them and the fine
+    /// tuning keeps the master tuning applied.
+    pub(crate) fn restore_channel_states(&mut self, states: &[ChannelState]) {
+        let channels = states.len().min(self.channel_states.len());
+        for (channel, state) in states[..channels].iter().enumerate() {
+            for event in state.to_events() {
+                self.send_event(SynthEvent::Channel(channel as u32, event));
+            }
+        }
+    }
+
+    /// Mutes or unmutes a channel. Muting a channel stops its active notes and
+    /// ignores new ones until it is unmuted.
+    pub(crate) fn set_channel_muted(&mut self, channel: u32, muted: bool) {
+        if !muted {
+            self.muted_channels.remove(&channel);
+        } else if self.muted_channels.insert(channel) {
+            self.track_keys(channel, &ChannelAudioEvent::AllNotesKilled);
+            self.group.send_event(SynthEvent::Channel(
+                channel,
+                ChannelAudioEvent::AllNotesKilled,
+            ));
+        }
+    }
+
+    /// Enables or disables a channel. Disabling a channel kills its active
+    /// notes and ignores all events sent to it until it is enabled again.
+    pub(crate) fn set_channel_enabled(&mut self, channel: u32, enabled: bool) {
+        if enabled {
+            self.disabled_channels.remove(&channel);
+        } else if !self.disabled_channels.contains(&channel) {
+            self.send_event(SynthEvent::Channel(
+                channel,
+                ChannelAudioEvent::AllNotesKilled,
+            ));
+            self.disabled_channels.insert(channel);
+        }
+    }
+
+    /// Releases the oldest notes of a channel until a new note on the given
+    /// key fits in its note limit.
+    fn enforce_note_limit(&mut self, channel: u32, key: u8) {
+        let Some(&limit) = self.note_limits.get(&channel) else {
+            return;
+        };
+        let Some(played) = self.played_keys.get(channel as usize) else {
+            return;
+        };
+
+        let mut held: Vec<(u64, u8)> = played
+            .iter()
+            .enumerate()
+            .filter(|&(k, _)| k != key as usize)
+            .filter_map(|(k, note)| note.map(|note| (note.start, k as u8)))
+            .collect();
+        if held.len() < limit as usize {
+            return;
+        }
+
+        held.sort_unstable();
+        let excess = held.len() + 1 - limit as usize;
+        for &(_, k) in &held[..excess] {
+            self.send_event(SynthEvent::Channel(
+                channel,
+                ChannelAudioEvent::NoteOff { key: k },
+            ));
+        }
+    }
+
+    fn choke(&mut self, channel: u32, key: u8) {
+        let Some(&id) = self.choke_groups.get(&(channel, key)) else {
+            return;
+        };
+
+        for (&(c, k), &group) in self.choke_groups.iter() {
+            if c == channel && k != key && group == id {
+                let k = self
+                    .played_keys
+                    .get_mut(channel as usize)
+                    .and_then(|played| played[k as usize & 127].take())
+                    .map_or(k, |note| note.key);
+                if let Some(keys) = self.active_keys.get_mut(channel as usize) {
+                    keys[k as usize & 127] = false;
+                }
+                self.group.send_event(SynthEvent::Channel(
+                    channel,
+                    ChannelAudioEvent::NoteOff { key: k },
+                ));
+            }
+        }
+    }
+
+    pub(crate) fn read_samples(&mut self, buffer: &mut [f32]) {
+        let params = self.params;
+        let channels = params.channels.count();
+        if self.max_note_duration > 0.0 {
+            let max_frames = (self.max_note_duration * params.sample_rate as f64) as u64;
+            self.release_expired_notes(max_frames);
+        }
+
+        if self.decimator.is_some() {
+            self.render_oversampled(buffer);
+        } else {
+            self.render(buffer);
+        }
+        self.effects.process(buffer, channels, params.sample_rate);
+        self.clock += (buffer.len() / channels as usize) as u64;
+    }
+
+    /// Renders audio at the oversampled rate and filters it down to the
+    /// output rate. The filter needs a few samples ahead of each output
+    /// sample, so slightly more audio than requested is rendered and the
+    /// rest is kept for the next read.
+    fn render_oversampled(&mut self, buffer: &mut [f32]) {
+        let mut oversampled = std::mem::take(&mut self.oversampled);
+        while self.decimated.len() < buffer.len() {
+            let missing = buffer.len() - self.decimated.len();
+            oversampled.clear();
+            oversampled.resize(missing * self.oversampling as usize, 0.0);
+            self.render(&mut oversampled);
+            if let Some(decimator) = &mut self.decimator {
+                decimator.process(&oversampled, &mut self.decimated);
+            }
+        }
+        self.oversampled = oversampled;
+
+        buffer.copy_from_slice(&self.decimated[..buffer.len()]);
+        self.decimated.drain(..buffer.len());
+    }
+
+    /// Renders audio at the sample rate of the channel group.
+    fn render(&mut self, buffer: &mut [f32]) {
+        let params = *self.group.stream_params();
+        let channels = params.channels.count();
+        if self.vibrato.is_needed(&self.channel_states) {
+            for chunk in buffer.chunks_mut(VIBRATO_STEP_FRAMES * channels as usize) {
+                let frames = chunk.len() / channels as usize;
+                let tunings = self
+                    .vibrato
+                    .step(&self.channel_states, frames, params.sample_rate);
+                for (channel, cents) in tunings {
+                    self.group.send_event(SynthEvent::Channel(
+                        channel,
+                        ChannelAudioEvent::Control(ControlEvent::FineTune(cents)),
+                    ));
+                }
+                self.group.read_samples(chunk);
+            }
+        } else {
+            self.group.read_samples(buffer);
+        }
+    }
+
+    /// Releases all notes that have been held for at least the given number
+    /// of frames.
+    fn release_expired_notes(&mut self, max_frames: u64) {
+        let mut expired = Vec::new();
+        for (channel, played) in self.played_keys.iter().enumerate() {
+            for (key, note) in played.iter().enumerate() {
+                if note.is_some_and(|note| self.clock - note.start >= max_frames) {
+                    expired.push((channel as u32, key as u8));
+                }
+            }
+        }
+
+        for (channel, key) in expired {
+            self.send_event(SynthEvent::Channel(
+                channel,
+                ChannelAudioEvent::NoteOff { key },
+            ));
+        }
+    }
+
+    /// Renders as many samples as fit in the ring buffer of the group and
+    /// returns their count.
+    pub(crate) fn fill_ring(&mut self) -> usize {
+        let Some(ring) = self.ring.clone() else {
+            return 0;
+        };
+
+        let channels = self.params.channels.count() as usize;
+        let free = ring.free() / channels * channels;
+        if free == 0 {
+            return 0;
+        }
+
+        ring.push(self.read_scratch(free))
+    }
+
+    /// Renders the given number of samples into the scratch buffer of the
+    /// group, which is reused between calls, and returns them.
+    pub(crate) fn read_scratch(&mut self, length: usize) -> &[f32] {
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.clear();
+        scratch.resize(length, 0.0);
+        self.read_samples(&mut scratch);
+        self.scratch = scratch;
+        &self.scratch
+    }
+
+    /// Makes sure the scratch buffer can hold the given number of samples
+    /// without reallocating.
+    pub(crate) fn prepare_for_length(&mut self, length: usize) {
+        self.scratch.clear();
+        self.scratch.reserve(length);
+    }
+
+    /// Sets the soundfonts of the group. When oversampling, they are loaded
+    /// again at the sample rate the group renders at, and the ones that fail
+    /// to load are left out.
+    pub(crate) fn set_soundfonts(&mut self, soundfonts: Vec<Arc<SoundfontInstance>>) {
+        if self.oversampling > 1 {
+            let params = convert_streamparams_to_c(self.group.stream_params());
+            (self.soundfonts, self.soundfont_views) = soundfonts
+                .into_iter()
+                .filter_map(|sf| sf.with_stream_params(params).map(|view| (sf, view)))
+                .unzip();
+        } else {
+            self.soundfont_views = to_dyn_soundfonts(&soundfonts);
+            self.soundfonts = soundfonts;
+        }
+        self.send_soundfonts();
+    }
+
+    /// Removes the given soundfont from the group, if it uses it.
+    pub(crate) fn remove_soundfont(&mut self, soundfont: &SoundfontInstance) {
+        if !self.soundfonts.iter().any(|sf| sf.id == soundfont.id) {
+            return;
+        }
+
+        let (soundfonts, views) = self
+            .soundfonts
+            .drain(..)
+            .zip(self.soundfont_views.drain(..))
+            .filter(|(sf, _)| sf.id != soundfont.id)
+            .unzip();
+        self.soundfonts = soundfonts;
+        self.soundfont_views = views;
+        self.send_soundfonts();
+    }
+
+    fn send_soundfonts(&mut self) {
+        self.group.send_event(SynthEvent::ChannelConfig(
+            ChannelConfigEvent::SetSoundfonts(LimitedSoundfont::wrap(
+                &self.soundfont_views,
+                &self.voice_limit,
+            )),
+        ));
+    }
+
+    pub(crate) fn set_layer_count(&mut self, layers: Option<usize>) {
+        self.layers = layers;
+        self.group.send_event(SynthEvent::ChannelConfig(
+            ChannelConfigEvent::SetLayerCount(layers),
+        ));
+    }
+
+    /// Rebuilds the channel group with or without the threadpool, keeping its
+    /// soundfonts, layer count and controller state.
+    pub(crate) fn set_use_threadpool(&mut self, use_threadpool: bool) {
+        if self.settings.use_threadpool == use_threadpool {
+            return;
+        }
+
+        self.settings.use_threadpool = use_threadpool;
+        self.group = self.settings.build(*self.group.stream_params());
+        self.active_keys = vec![[false; 128]; self.settings.channel_count as usize];
+        self.played_keys = vec![[None; 128]; self.settings.channel_count as usize];
+        self.send_soundfonts();
+        self.set_layer_count(self.layers);
+        let states = std::mem::replace(
+            &mut self.channel_states,
+            vec![ChannelState::new(); self.settings.channel_count as usize],
+        );
+        self.restore_channel_states(&states);
+        self.set_master_tuning(self.vibrato.master_tuning());
+    }
+
+    /// Rebuilds the channel group with new output audio parameters, keeping
+    /// its soundfonts, layer count, oversampling and controller state.
+    /// Soundfonts that were loaded with different parameters are loaded again
+    /// for this group. Returns false if any of them fails to load, in which
+    /// case the group is left unchanged.
+    pub(crate) fn set_stream_params(&mut self, params: XSynth_StreamParams) -> bool {
+        self.rebuild(convert_streamparams_to_rust(params), self.oversampling)
+    }
+
+    /// Rebuilds the channel group to render at the given multiple of its
+    /// output sample rate, keeping its soundfonts, layer count and controller
+    /// state. Returns false if any soundfont fails to load at the new rate,
+    /// in which case the group is left unchanged.
+    pub(crate) fn set_oversampling(&mut self, oversampling: u16) -> bool {
+        if oversampling == self.oversampling {
+            return true;
+        }
+        self.rebuild(self.params, oversampling)
+    }
+
+    fn rebuild(&mut self, params: AudioStreamParams, oversampling: u16) -> bool {
+        let internal = XSynth_StreamParams {
+            sample_rate: params.sample_rate * oversampling as u32,
+            audio_channels: params.channels.count(),
+        };
+        let mut views = Vec::with_capacity(self.soundfonts.len());
+        for sf in self.soundfonts.iter() {
+            match sf.with_stream_params(internal) {
+                Some(view) => views.push(view),
+                None => return false,
+            }
+        }
+
+        self.build_group(params, oversampling);
+        self.active_keys = vec![[false; 128]; self.settings.channel_count as usize];
+        self.played_keys = vec![[None; 128]; self.settings.channel_count as usize];
+        self.soundfont_views = views;
+        self.send_soundfonts();
+        self.set_layer_count(self.layers);
+        let states = std::mem::replace(
+            &mut self.channel_states,
+            vec![ChannelState::new(); self.settings.channel_count as usize],
+        );
+        self.restore_channel_states(&states);
+        self.set_master_tuning(self.vibrato.master_tuning());
+        true
+    }
+}
 
 /// Handle of an internal ChannelGroup instance in XSynth.
 #[repr(C)]
@@ -13,30 +712,390 @@ pub struct XSynth_ChannelGroup {
 }
 
 impl XSynth_ChannelGroup {
-    pub(crate) fn from(group: ChannelGroup) -> Self {
-        let group = Box::into_raw(Box::new(group));
+    pub(crate) fn null() -> Self {
         Self {
-            group: group as *mut c_void,
+            group: std::ptr::null_mut(),
         }
     }
 
+    pub(crate) fn from(instance: GroupInstance) -> Self {
+        let group = Box::into_raw(Box::new(instance)) as *mut c_void;
+        GROUPS.insert(group);
+        Self { group }
+    }
+
     pub(crate) fn drop(self) {
-        let group = self.group as *mut ChannelGroup;
+        GROUPS.remove(self.group);
+        let group = self.group as *mut GroupInstance;
         unsafe { drop(Box::from_raw(group)) }
     }
 
-    pub(crate) fn as_ref(&self) -> &ChannelGroup {
-        let group = self.group as *mut ChannelGroup;
+    pub(crate) fn as_ref(&self) -> &GroupInstance {
+        let group = self.group as *mut GroupInstance;
         unsafe { &*group }
     }
 
     #[allow(clippy::mut_from_ref)]
-    pub(crate) fn as_mut(&self) -> &mut ChannelGroup {
-        let group = self.group as *mut ChannelGroup;
+    pub(crate) fn as_mut(&self) -> &mut GroupInstance {
+        let group = self.group as *mut GroupInstance;
         unsafe { &mut *group }
     }
 }
 
+/// Handle of a ring buffer filled by a channel group.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct XSynth_RingBuffer {
+    pub ring: *mut c_void,
+}
+
+impl XSynth_RingBuffer {
+    pub(crate) fn null() -> Self {
+        Self {
+            ring: std::ptr::null_mut(),
+        }
+    }
+
+    pub(crate) fn from(ring: Arc<RingBuffer>) -> Self {
+        let ring = Box::into_raw(Box::new(ring));
+        Self {
+            ring: ring as *mut c_void,
+        }
+    }
+
+    pub(crate) fn drop(self) {
+        let ring = self.ring as *mut Arc<RingBuffer>;
+        unsafe { drop(Box::from_raw(ring)) }
+    }
+
+    pub(crate) fn as_ref(&self) -> &RingBuffer {
+        let ring = self.ring as *mut Arc<RingBuffer>;
+        unsafe { &*ring }
+    }
+}
+
+/// Handle of a saved controller state of a channel group.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct XSynth_ChannelState {
+    pub state: *mut c_void,
+}
+
+impl XSynth_ChannelState {
+    pub(crate) fn null() -> Self {
+        Self {
+            state: std::ptr::null_mut(),
+        }
+    }
+
+    pub(crate) fn from(states: Vec<ChannelState>) -> Self {
+        let states = Box::into_raw(Box::new(states));
+        Self {
+            state: states as *mut c_void,
+        }
+    }
+
+    pub(crate) fn drop(self) {
+        let states = self.state as *mut Vec<ChannelState>;
+        unsafe { drop(Box::from_raw(states)) }
+    }
+
+    pub(crate) fn as_ref(&self) -> &[ChannelState] {
+        let states = self.state as *mut Vec<ChannelState>;
+        unsafe { &*states }
+    }
+}
+
+/// The samples of a soundfont, loaded with one set of audio parameters.
+#[derive(Debug)]
+pub(crate) struct SoundfontSamples {
+    soundfont: RwLock<Arc<SampleSoundfont>>,
+    params: XSynth_StreamParams,
+    stream_params: AudioStreamParams,
+}
+
+impl SoundfontSamples {
+    fn new(soundfont: SampleSoundfont, params: XSynth_StreamParams) -> Arc<Self> {
+        Arc::new(Self {
+            soundfont: RwLock::new(Arc::new(soundfont)),
+            params,
+            stream_params: convert_streamparams_to_rust(params),
+        })
+    }
+
+    fn get(&self) -> Arc<SampleSoundfont> {
+        self.soundfont
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn replace(&self, soundfont: SampleSoundfont) {
+        *self.soundfont.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(soundfont);
+    }
+}
+
+/// A loaded soundfont, along with the information needed to load it again.
+/// Channel groups use this directly as their soundfont, so that replacing the
+/// inner soundfont affects every group it was sent to.
+#[derive(Debug)]
+pub(crate) struct SoundfontInstance {
+    samples: Arc<SoundfontSamples>,
+    /// The samples loaded again for channel groups using other audio
+    /// parameters. They are kept alive by the groups using them.
+    other_samples: Mutex<Vec<Weak<SoundfontSamples>>>,
+    /// Shared by all handles pointing to this soundfont.
+    pub id: u64,
+    pub path: PathBuf,
+    /// The path as a C string, kept for XSynth_Soundfont_GetPath.
+    pub c_path: CString,
+    pub options: XSynth_SoundfontOptions,
+    /// The root key of a WAV file loaded using
+    /// XSynth_Soundfont_LoadWavAsInstrument, in which case the path is the
+    /// one of the WAV file.
+    pub root_key: Option<u8>,
+    /// Maximum number of zones a single note may trigger, or 0 for no limit.
+    pub max_zones: AtomicU32,
+    /// Linear gain of the voices of the soundfont, as f32 bits.
+    pub gain: Arc<AtomicU32>,
+    /// The (bank, preset) pairs that do not produce any voices.
+    muted_presets: RwLock<HashSet<(u8, u8)>>,
+}
+
+static NEXT_SOUNDFONT_ID: AtomicU64 = AtomicU64::new(0);
+
+impl SoundfontInstance {
+    pub(crate) fn new(
+        soundfont: SampleSoundfont,
+        path: PathBuf,
+        options: XSynth_SoundfontOptions,
+    ) -> Self {
+        Self {
+            samples: SoundfontSamples::new(soundfont, options.stream_params),
+            other_samples: Mutex::new(Vec::new()),
+            id: NEXT_SOUNDFONT_ID.fetch_add(1, Ordering::Relaxed),
+            c_path: CString::new(path.to_string_lossy().into_owned()).unwrap_or_default(),
+            path,
+            options,
+            root_key: None,
+            max_zones: AtomicU32::new(0),
+            gain: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+            muted_presets: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// Returns this soundfont as used with the given audio parameters. If
+    /// they differ from the ones it was loaded with, the samples are loaded
+    /// again, unless another channel group already uses them. The settings
+    /// of the soundfont, such as its muted presets, stay shared.
+    pub(crate) fn with_stream_params(
+        self: &Arc<Self>,
+        params: XSynth_StreamParams,
+    ) -> Option<Arc<dyn SoundfontBase>> {
+        if self.options.stream_params == params {
+            return Some(self.clone() as Arc<dyn SoundfontBase>);
+        }
+
+        let mut other_samples = self.other_samples.lock().unwrap_or_else(|e| e.into_inner());
+        other_samples.retain(|samples| samples.strong_count() > 0);
+        let existing = other_samples
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|samples| samples.params == params);
+        let samples = match existing {
+            Some(samples) => samples,
+            None => {
+                let options = XSynth_SoundfontOptions {
+                    stream_params: params,
+                    ..self.options
+                };
+                let samples = SoundfontSamples::new(self.load(&options)?, params);
+                other_samples.push(Arc::downgrade(&samples));
+                samples
+            }
+        };
+
+        let view = SoundfontView {
+            instance: self.clone(),
+            samples,
+        };
+        Some(Arc::new(view) as Arc<dyn SoundfontBase>)
+    }
+
+    pub(crate) fn soundfont(&self) -> Arc<SampleSoundfont> {
+        self.samples.get()
+    }
+
+    /// Loads the soundfont again from its path, with the given options.
+    fn load(&self, options: &XSynth_SoundfontOptions) -> Option<SampleSoundfont> {
+        match self.root_key {
+            Some(root_key) => load_wav_instrument(&self.path, root_key, options).ok(),
+            None => load_soundfont(&self.path, options),
+        }
+    }
+
+    /// Loads the soundfont from its path again, for every set of audio
+    /// parameters it is used with. Returns false if any of them fails to
+    /// load, in which case nothing is replaced.
+    pub(crate) fn reload(&self) -> bool {
+        let other_samples = self.other_samples.lock().unwrap_or_else(|e| e.into_inner());
+        let mut all_samples = vec![self.samples.clone()];
+        all_samples.extend(other_samples.iter().filter_map(Weak::upgrade));
+
+        let mut loaded = Vec::with_capacity(all_samples.len());
+        for samples in all_samples.iter() {
+            let options = XSynth_SoundfontOptions {
+                stream_params: samples.params,
+                ..self.options
+            };
+            match self.load(&options) {
+                Some(soundfont) => loaded.push(soundfont),
+                None => return false,
+            }
+        }
+
+        for (samples, soundfont) in all_samples.iter().zip(loaded) {
+            samples.replace(soundfont);
+        }
+        true
+    }
+
+    pub(crate) fn muted_presets(&self) -> RwLockReadGuard<'_, HashSet<(u8, u8)>> {
+        self.muted_presets.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn set_preset_muted(&self, bank: u8, preset: u8, muted: bool) {
+        let mut muted_presets = self
+            .muted_presets
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        if muted {
+            muted_presets.insert((bank, preset));
+        } else {
+            muted_presets.remove(&(bank, preset));
+        }
+    }
+
+    pub(crate) fn unmute_all_presets(&self) {
+        self.muted_presets
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+
+    /// Applies the zone limit and the gain of the soundfont to the spawners
+    /// of a note.
+    fn adjust_spawners(
+        &self,
+        mut spawners: Vec<Box<dyn VoiceSpawner>>,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        let max_zones = self.max_zones.load(Ordering::Relaxed) as usize;
+        if max_zones > 0 {
+            spawners.truncate(max_zones);
+        }
+        GainSpawner::wrap(spawners, &self.gain, 0.0)
+    }
+
+    fn attack_spawners(
+        &self,
+        samples: &SoundfontSamples,
+        bank: u8,
+        preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        if self.muted_presets().contains(&(bank, preset)) {
+            return Vec::new();
+        }
+        self.adjust_spawners(
+            samples
+                .get()
+                .get_attack_voice_spawners_at(bank, preset, key, vel),
+        )
+    }
+
+    fn release_spawners(
+        &self,
+        samples: &SoundfontSamples,
+        bank: u8,
+        preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        if self.muted_presets().contains(&(bank, preset)) {
+            return Vec::new();
+        }
+        self.adjust_spawners(
+            samples
+                .get()
+                .get_release_voice_spawners_at(bank, preset, key, vel),
+        )
+    }
+}
+
+impl SoundfontBase for SoundfontInstance {
+    fn stream_params(&self) -> &AudioStreamParams {
+        &self.samples.stream_params
+    }
+
+    fn get_attack_voice_spawners_at(
+        &self,
+        bank: u8,
+        preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        self.attack_spawners(&self.samples, bank, preset, key, vel)
+    }
+
+    fn get_release_voice_spawners_at(
+        &self,
+        bank: u8,
+        preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        self.release_spawners(&self.samples, bank, preset, key, vel)
+    }
+}
+
+/// A soundfont used with other audio parameters than the ones it was loaded
+/// with. Only the samples are its own, everything else is read from the
+/// soundfont it was created from.
+#[derive(Debug)]
+struct SoundfontView {
+    instance: Arc<SoundfontInstance>,
+    samples: Arc<SoundfontSamples>,
+}
+
+impl SoundfontBase for SoundfontView {
+    fn stream_params(&self) -> &AudioStreamParams {
+        &self.samples.stream_params
+    }
+
+    fn get_attack_voice_spawners_at(
+        &self,
+        bank: u8,
+        preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        self.instance
+            .attack_spawners(&self.samples, bank, preset, key, vel)
+    }
+
+    fn get_release_voice_spawners_at(
+        &self,
+        bank: u8,
+        preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        self.instance
+            .release_spawners(&self.samples, bank, preset, key, vel)
+    }
+}
+
 /// Handle of an internal Soundfont object in XSynth.
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -45,25 +1104,245 @@ pub struct XSynth_Soundfont {
 }
 
 impl XSynth_Soundfont {
-    pub(crate) fn from(sf: Arc<SampleSoundfont>) -> Self {
-        let sf = Box::into_raw(Box::new(sf));
+    pub(crate) fn null() -> Self {
         Self {
-            soundfont: sf as *mut c_void,
+            soundfont: std::ptr::null_mut(),
         }
     }
 
+    pub(crate) fn from(sf: Arc<SoundfontInstance>) -> Self {
+        let sf = Box::into_raw(Box::new(sf)) as *mut c_void;
+        SOUNDFONTS.insert(sf);
+        Self { soundfont: sf }
+    }
+
     pub(crate) fn drop(self) {
-        let soundfont = self.soundfont as *mut Arc<SampleSoundfont>;
+        SOUNDFONTS.remove(self.soundfont);
+        let soundfont = self.soundfont as *mut Arc<SoundfontInstance>;
         unsafe { drop(Box::from_raw(soundfont)) }
     }
 
-    pub(crate) fn clone(&self) -> Arc<dyn SoundfontBase> {
+    pub(crate) fn as_ref(&self) -> &SoundfontInstance {
+        let soundfont = self.soundfont as *mut Arc<SoundfontInstance>;
+        unsafe { &*soundfont }
+    }
+
+    pub(crate) fn clone(&self) -> Arc<SoundfontInstance> {
         unsafe {
-            let sf = self.soundfont as *mut Arc<SampleSoundfont>;
+            let sf = self.soundfont as *mut Arc<SoundfontInstance>;
             let sf = &*sf;
             sf.clone()
         }
     }
+
+    /// Returns whether the given soundfont was loaded from this handle.
+    pub(crate) fn is_same(&self, sf: &SoundfontInstance) -> bool {
+        self.as_ref().id == sf.id
+    }
+}
+
+/// The renderer behind a realtime synthesizer handle.
+pub(crate) enum RealtimeBackend {
+    /// Renders directly to an audio output device.
+    Device {
+        synth: RealtimeSynth,
+        device: Device,
+        stream_config: SupportedStreamConfig,
+    },
+    /// Renders only when samples are requested by the caller.
+    Headless {
+        group: ChannelGroup,
+        ignore_range: RangeInclusive<u8>,
+    },
+}
+
+/// Internal state of a realtime synthesizer, owned by its handle.
+pub(crate) struct RealtimeInstance {
+    pub backend: RealtimeBackend,
+    pub settings: RealtimeSettings,
+    pub soundfonts: Vec<Arc<SoundfontInstance>>,
+    pub layers: Option<usize>,
+    /// Number of audio frames rendered by a headless synth so far.
+    pub clock: u64,
+    /// Events waiting to be applied at their frame, sorted by time.
+    scheduled: VecDeque<(u64, SynthEvent)>,
+    pub midi_parser: MidiParser,
+    /// Render load at which the overload callback is called, as f64 bits.
+    pub overload_threshold: Arc<AtomicU64>,
+    pub overload: Option<OverloadMonitor>,
+    pub voice_limit: Arc<VoiceLimit>,
+    /// The output volume of a headless synth, applied after all processing.
+    pub volume: SmoothedGain,
+    /// The volume of a synth playing to an audio device as f32 bits, applied
+    /// to its voices.
+    device_volume: Arc<AtomicU32>,
+}
+
+impl RealtimeInstance {
+    pub(crate) fn send_event(&mut self, event: SynthEvent) {
+        if let RealtimeBackend::Headless { ignore_range, .. } = &self.backend {
+            if let SynthEvent::Channel(_, ChannelAudioEvent::NoteOn { vel, .. }) = &event {
+                if ignore_range.contains(vel) {
+                    return;
+                }
+            }
+        }
+        match &mut self.backend {
+            RealtimeBackend::Device { synth, .. } => synth.send_event(event),
+            RealtimeBackend::Headless { group, .. } => group.send_event(event),
+        }
+    }
+
+    /// Sets the output volume, which is smoothed to avoid clicks.
+    pub(crate) fn set_volume(&mut self, volume: f32) {
+        self.volume.target = volume;
+        self.device_volume
+            .store(volume.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Schedules an event to be applied once the clock reaches the given
+    /// frame. Returns false without applying the event for device backed
+    /// synths, as they have no accessible clock.
+    pub(crate) fn send_event_at(&mut self, event: SynthEvent, time: u64) -> bool {
+        match &self.backend {
+            RealtimeBackend::Device { .. } => false,
+            RealtimeBackend::Headless { .. } => {
+                let index = self.scheduled.partition_point(|(t, _)| *t <= time);
+                self.scheduled.insert(index, (time, event));
+                true
+            }
+        }
+    }
+
+    /// Renders audio of a headless synth, applying the scheduled events at
+    /// their exact frame. Returns false without touching the buffer for
+    /// device backed synths, as their audio is read by the device.
+    pub(crate) fn read_samples(&mut self, buffer: &mut [f32]) -> bool {
+        if let RealtimeBackend::Device { .. } = &self.backend {
+            return false;
+        }
+
+        let channels = self.stream_params().channels.count() as usize;
+        let frames = buffer.len() / channels;
+        let mut rendered = 0;
+        while rendered < frames {
+            while self
+                .scheduled
+                .front()
+                .is_some_and(|(t, _)| *t <= self.clock)
+            {
+                if let Some((_, event)) = self.scheduled.pop_front() {
+                    self.send_event(event);
+                }
+            }
+
+            let until_next = match self.scheduled.front() {
+                Some((t, _)) => (t - self.clock).min(frames as u64) as usize,
+                None => frames,
+            };
+            let end = frames.min(rendered + until_next);
+            if let RealtimeBackend::Headless { group, .. } = &mut self.backend {
+                group.read_samples(&mut buffer[rendered * channels..end * channels]);
+            }
+            self.clock += (end - rendered) as u64;
+            rendered = end;
+        }
+        let sample_rate = self.stream_params().sample_rate;
+        self.volume.process(
+            &mut buffer[..frames * channels],
+            channels as u16,
+            sample_rate,
+        );
+        buffer[frames * channels..].fill(0.0);
+        true
+    }
+
+    /// Returns the number of timestamped events waiting to be applied.
+    pub(crate) fn scheduled_count(&self) -> usize {
+        self.scheduled.len()
+    }
+
+    pub(crate) fn voice_count(&self) -> u64 {
+        match &self.backend {
+            RealtimeBackend::Device { synth, .. } => synth.get_stats().voice_count(),
+            RealtimeBackend::Headless { group, .. } => group.voice_count(),
+        }
+    }
+
+    pub(crate) fn stream_params(&self) -> AudioStreamParams {
+        match &self.backend {
+            RealtimeBackend::Device { synth, .. } => synth.stream_params(),
+            RealtimeBackend::Headless { group, .. } => *group.stream_params(),
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.scheduled.clear();
+        match &mut self.backend {
+            RealtimeBackend::Device { synth, .. } => synth.get_senders().reset_synth(),
+            RealtimeBackend::Headless { group, .. } => {
+                for channel in 0..self.settings.group.channel_count {
+                    group.send_event(SynthEvent::Channel(
+                        channel,
+                        ChannelAudioEvent::AllNotesKilled,
+                    ));
+                    group.send_event(SynthEvent::Channel(
+                        channel,
+                        ChannelAudioEvent::ResetControl,
+                    ));
+                }
+            }
+        }
+    }
+
+    pub(crate) fn set_soundfonts(&mut self, soundfonts: Vec<Arc<SoundfontInstance>>) {
+        let mut wrapped =
+            LimitedSoundfont::wrap(&to_dyn_soundfonts(&soundfonts), &self.voice_limit);
+        // The audio of a device backed synth never passes through the
+        // wrapper, so its volume is applied to every voice instead.
+        if let RealtimeBackend::Device { .. } = &self.backend {
+            wrapped = GainSoundfont::wrap(&wrapped, &self.device_volume);
+        }
+        self.send_event(SynthEvent::ChannelConfig(
+            ChannelConfigEvent::SetSoundfonts(wrapped),
+        ));
+        self.soundfonts = soundfonts;
+    }
+
+    /// Rebuilds the synthesizer with new drum channels, keeping its
+    /// soundfonts and layer count. Active notes are stopped.
+    pub(crate) fn set_drum_channels(&mut self, drums_channels: Vec<u32>) {
+        self.settings.group.drums_channels = drums_channels;
+        match &mut self.backend {
+            RealtimeBackend::Device {
+                synth,
+                device,
+                stream_config,
+            } => {
+                *synth = RealtimeSynth::open(
+                    self.settings.realtime_config(),
+                    device,
+                    stream_config.clone(),
+                );
+                if let Some(monitor) = &mut self.overload {
+                    monitor.restart(self.overload_threshold.clone(), synth.get_stats());
+                }
+            }
+            RealtimeBackend::Headless { group, .. } => {
+                *group = self.settings.group.build(*group.stream_params());
+            }
+        }
+        self.scheduled.clear();
+        self.set_soundfonts(self.soundfonts.clone());
+        self.set_layer_count(self.layers);
+    }
+
+    pub(crate) fn set_layer_count(&mut self, layers: Option<usize>) {
+        self.layers = layers;
+        self.send_event(SynthEvent::ChannelConfig(
+            ChannelConfigEvent::SetLayerCount(layers),
+        ));
+    }
 }
 
 /// Handle of an internal RealtimeSynth instance in XSynth.
@@ -74,26 +1353,47 @@ pub struct XSynth_RealtimeSynth {
 }
 
 impl XSynth_RealtimeSynth {
-    pub(crate) fn from(synth: RealtimeSynth) -> Self {
-        let synth = Box::into_raw(Box::new(synth));
+    pub(crate) fn null() -> Self {
         Self {
-            synth: synth as *mut c_void,
+            synth: std::ptr::null_mut(),
         }
     }
 
+    pub(crate) fn from(backend: RealtimeBackend, settings: RealtimeSettings) -> Self {
+        let voice_limit = VoiceLimit::new(settings.group.fade_out_killing);
+        let instance = RealtimeInstance {
+            backend,
+            settings,
+            soundfonts: Vec::new(),
+            layers: None,
+            clock: 0,
+            scheduled: VecDeque::new(),
+            midi_parser: MidiParser::default(),
+            overload_threshold: Arc::new(AtomicU64::new(DEFAULT_OVERLOAD_THRESHOLD.to_bits())),
+            overload: None,
+            voice_limit,
+            volume: SmoothedGain::new(1.0),
+            device_volume: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+        };
+        let synth = Box::into_raw(Box::new(instance)) as *mut c_void;
+        REALTIME_SYNTHS.insert(synth);
+        Self { synth }
+    }
+
     pub(crate) fn drop(self) {
-        let synth = self.synth as *mut RealtimeSynth;
+        REALTIME_SYNTHS.remove(self.synth);
+        let synth = self.synth as *mut RealtimeInstance;
         unsafe { drop(Box::from_raw(synth)) }
     }
 
-    pub(crate) fn as_ref(&self) -> &RealtimeSynth {
-        let synth = self.synth as *mut RealtimeSynth;
+    pub(crate) fn as_ref(&self) -> &RealtimeInstance {
+        let synth = self.synth as *mut RealtimeInstance;
         unsafe { &*synth }
     }
 
     #[allow(clippy::mut_from_ref)]
-    pub(crate) fn as_mut(&self) -> &mut RealtimeSynth {
-        let synth = self.synth as *mut RealtimeSynth;
+    pub(crate) fn as_mut(&self) -> &mut RealtimeInstance {
+        let synth = self.synth as *mut RealtimeInstance;
         unsafe { &mut *synth }
     }
 }
@@ -1,19 +1,42 @@
 #![allow(clippy::missing_safety_doc)]
 #![allow(static_mut_refs)]
 
+use effects::{analyze_levels, Compressor, DcFilter, FadeOut};
+use error::{catch_panic, set_last_error};
+use registry::{GROUPS, SOUNDFONTS};
+use resample::{Resampler, ResamplerQuality};
+use ring::RingBuffer;
+use std::{
+    ffi::{c_char, c_void, CString},
+    sync::Arc,
+};
 use xsynth_core::{
-    channel::{ChannelConfigEvent, ChannelInitOptions},
-    channel_group::{ChannelGroup, ChannelGroupConfig},
+    channel::{ChannelAudioEvent, ControlEvent},
+    channel_group::SynthEvent,
     AudioPipe,
 };
 
+mod channel_state;
 pub(crate) mod consts;
+mod effects;
+mod error;
+mod midi;
+mod overload;
 mod realtime;
-mod soundfont;
+mod registry;
+mod resample;
+mod ring;
+pub(crate) mod soundfont;
+mod synth;
+#[cfg(test)]
+mod test_utils;
 mod utils;
+mod vibrato;
+mod voices;
 pub use consts::*;
+pub use error::*;
+pub use synth::*;
 pub use utils::*;
-use xsynth_realtime::SynthEvent;
 
 mod handles;
 pub use handles::*;
@@ -24,9 +47,67 @@ pub use handles::*;
 /// The XSynth version. For example, 0x010102 (hex), would be version 1.1.2
 #[no_mangle]
 pub extern "C" fn XSynth_GetVersion() -> u32 {
-    env!("XSYNTHVERSION")
-        .parse()
-        .unwrap_or_else(|_| panic!("Could not parse version number."))
+    catch_panic(0, || {
+        env!("XSYNTHVERSION")
+            .parse()
+            .unwrap_or_else(|_| panic!("Could not parse version number."))
+    })
+}
+
+/// Returns the version of the xsynth-core library this build of XSynth uses
+///
+/// --Returns--
+/// A null terminated string of the version, for example "0.1.0". The string
+/// is static and must not be freed.
+#[no_mangle]
+pub extern "C" fn XSynth_GetCoreVersionString() -> *const c_char {
+    catch_panic(std::ptr::null(), || {
+        concat!(env!("XSYNTHCOREVERSION"), "\0").as_ptr() as *const c_char
+    })
+}
+
+/// Returns the version of the xsynth-realtime library this build of XSynth
+/// uses
+///
+/// --Returns--
+/// A null terminated string of the version, for example "0.1.0". The string
+/// is static and must not be freed.
+#[no_mangle]
+pub extern "C" fn XSynth_GetRealtimeVersionString() -> *const c_char {
+    catch_panic(std::ptr::null(), || {
+        concat!(env!("XSYNTHREALTIMEVERSION"), "\0").as_ptr() as *const c_char
+    })
+}
+
+/// Enables or disables handle mode. In handle mode, soundfonts are referenced
+/// only through their handles, without being tracked in a global list. This
+/// avoids taking a global lock whenever a soundfont handle is created or
+/// freed, which helps hosts that load soundfonts from many threads.
+///
+/// In handle mode, XSynth_Soundfont_IsValid can no longer detect freed
+/// handles, and only checks that the handle is not null.
+///
+/// This function must be called before any soundfont is loaded. The mode can
+/// only be changed again once all soundfont handles have been removed,
+/// including the ones loaded in handle mode.
+///
+/// --Parameters--
+/// - enabled: Whether handle mode should be used (false by default)
+///
+/// --Returns--
+/// True if the mode was changed, or false if any soundfont handle is still
+/// alive. In that case, the reason can be retrieved using
+/// XSynth_GetLastError.
+#[no_mangle]
+pub extern "C" fn XSynth_Config_UseHandleMode(enabled: bool) -> bool {
+    catch_panic(false, || {
+        if SOUNDFONTS.set_enabled(!enabled) {
+            true
+        } else {
+            set_last_error("Handle mode can not be changed while soundfonts are loaded");
+            false
+        }
+    })
 }
 
 /// Parameters of the output audio
@@ -34,6 +115,7 @@ pub extern "C" fn XSynth_GetVersion() -> u32 {
 /// - audio_channels: Number of audio channels
 ///         Supported: AUDIO_CHANNELS_MONO (mono), AUDIO_CHANNELS_STEREO (stereo)
 #[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct XSynth_StreamParams {
     pub sample_rate: u32,
     pub audio_channels: u16,
@@ -99,29 +181,41 @@ pub extern "C" fn XSynth_GenDefault_GroupOptions() -> XSynth_GroupOptions {
 /// --Returns--
 /// This function will return the handle of the created channel group. This will be
 /// necessary to use other XSynth_ChannelGroup_* functions, as they are specific to
-/// each group.
+/// each group. If the channel group could not be created, a null handle is returned
+/// and the reason can be retrieved using XSynth_GetLastError.
 #[no_mangle]
 pub extern "C" fn XSynth_ChannelGroup_Create(options: XSynth_GroupOptions) -> XSynth_ChannelGroup {
-    unsafe {
-        let channel_init_options = ChannelInitOptions {
-            fade_out_killing: options.fade_out_killing,
-            drums_only: false,
-        };
-
+    catch_panic(XSynth_ChannelGroup::null(), || unsafe {
         let drumvec =
             std::slice::from_raw_parts(options.drum_channels, options.drum_channels_count as usize);
 
-        let config = ChannelGroupConfig {
-            channel_init_options,
+        let settings = GroupSettings {
             channel_count: options.channels,
             drums_channels: Vec::from(drumvec),
-            audio_params: convert_streamparams_to_rust(options.stream_params),
             use_threadpool: options.use_threadpool,
+            fade_out_killing: options.fade_out_killing,
         };
 
-        let new = ChannelGroup::new(config);
-        XSynth_ChannelGroup::from(new)
-    }
+        let audio_params = convert_streamparams_to_rust(options.stream_params);
+        XSynth_ChannelGroup::from(GroupInstance::new(settings, audio_params))
+    })
+}
+
+/// Creates a copy of the desired channel group, which can be used
+/// independently of the original one, for example to render variations of
+/// the same MIDI in parallel. The copy uses the same soundfonts and has the
+/// same settings and controller state, but no active voices.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance to copy
+///
+/// --Returns--
+/// This function will return the handle of the new channel group.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_Clone(handle: XSynth_ChannelGroup) -> XSynth_ChannelGroup {
+    catch_panic(XSynth_ChannelGroup::null(), || {
+        XSynth_ChannelGroup::from(handle.as_ref().duplicate())
+    })
 }
 
 /// Returns the active voice count of the desired channel group.
@@ -133,7 +227,21 @@ pub extern "C" fn XSynth_ChannelGroup_Create(options: XSynth_GroupOptions) -> XS
 /// A 64bit integer of the voice count
 #[no_mangle]
 pub extern "C" fn XSynth_ChannelGroup_VoiceCount(handle: XSynth_ChannelGroup) -> u64 {
-    handle.as_ref().voice_count()
+    catch_panic(0, || handle.as_ref().group.voice_count())
+}
+
+/// Returns whether the desired channel group has any active voices, which is
+/// useful for deciding whether to keep rendering, for example at the end of an
+/// export.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+///
+/// --Returns--
+/// True if at least one voice is active, false otherwise
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_HasActiveVoices(handle: XSynth_ChannelGroup) -> bool {
+    catch_panic(false, || handle.as_ref().group.voice_count() > 0)
 }
 
 /// Sends a MIDI event to the desired channel group.
@@ -169,8 +277,170 @@ pub extern "C" fn XSynth_ChannelGroup_SendEvent(
     event: u16,
     params: u16,
 ) {
-    let ev = convert_event(channel, event, params);
-    handle.as_mut().send_event(ev);
+    catch_panic((), || {
+        let ev = convert_event(channel, event, params);
+        handle.as_mut().send_event(ev);
+    })
+}
+
+/// Sends a SysEx message to the desired channel group.
+///
+/// Currently only the standard reset messages are supported (GM System On,
+/// GM2 System On, GS Reset and XG System On), which stop all notes and reset
+/// the controllers and program of every channel. All other messages are
+/// ignored.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - data: Pointer to the bytes of the SysEx message, with or without the
+///         leading 0xF0 and trailing 0xF7 bytes
+/// - length: The length of the above array
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_ChannelGroup_SendSysEx(
+    handle: XSynth_ChannelGroup,
+    data: *const u8,
+    length: u64,
+) {
+    catch_panic((), || unsafe {
+        if data.is_null() {
+            return;
+        }
+
+        let data = std::slice::from_raw_parts(data, length as usize);
+        if is_reset_sysex(data) {
+            handle.as_mut().reset();
+        }
+    })
+}
+
+/// Sends a high resolution (MIDI 2.0) control change event to the desired
+/// channel group.
+///
+/// XSynth processes controllers with 7bit resolution, so the value is sent
+/// as the controller's MSB. For controllers 0-31, which have a matching LSB
+/// controller in MIDI 1.0 (32-63), the next 7 bits are sent to it as well.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - channel: The number of the MIDI channel to send the event to (MIDI channel 1 is 0)
+/// - controller: The controller number (0-127)
+/// - value: The 32bit controller value
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SendControlChange32(
+    handle: XSynth_ChannelGroup,
+    channel: u32,
+    controller: u8,
+    value: u32,
+) {
+    catch_panic((), || {
+        let controller = controller.min(127);
+        let msb = (value >> 25) as u8;
+        handle.as_mut().send_event(SynthEvent::Channel(
+            channel,
+            ChannelAudioEvent::Control(ControlEvent::Raw(controller, msb)),
+        ));
+
+        if controller < 32 {
+            let lsb = ((value >> 18) & 127) as u8;
+            handle.as_mut().send_event(SynthEvent::Channel(
+                channel,
+                ChannelAudioEvent::Control(ControlEvent::Raw(controller + 32, lsb)),
+            ));
+        }
+    })
+}
+
+/// Sends a high resolution (MIDI 2.0) pitch wheel event to the desired
+/// channel group. Unlike MIDI_EVENT_PITCH, the value is not quantized to
+/// 14bit.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - channel: The number of the MIDI channel to send the event to (MIDI channel 1 is 0)
+/// - value: The 32bit pitch wheel position (0x80000000 = normal/middle)
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SendPitchBend32(
+    handle: XSynth_ChannelGroup,
+    channel: u32,
+    value: u32,
+) {
+    catch_panic((), || {
+        let center = 0x80000000u32 as f64;
+        let val = ((value as f64 - center) / center) as f32;
+        handle.as_mut().send_event(SynthEvent::Channel(
+            channel,
+            ChannelAudioEvent::Control(ControlEvent::PitchBendValue(val)),
+        ));
+    })
+}
+
+/// Sets the mod wheel (CC1) position of a channel of the desired channel
+/// group. The mod wheel controls a vibrato applied to all notes of the
+/// channel, at a rate of 5Hz and with a depth of up to 50 cents when the
+/// wheel is fully up. The vibrato is also applied when the mod wheel is
+/// moved using MIDI_EVENT_CONTROL. The default position is 0 (no vibrato).
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - channel: The number of the MIDI channel (MIDI channel 1 is 0)
+/// - value: The mod wheel position (0-127)
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetModWheel(
+    handle: XSynth_ChannelGroup,
+    channel: u32,
+    value: u8,
+) {
+    catch_panic((), || {
+        handle.as_mut().send_event(SynthEvent::Channel(
+            channel,
+            ChannelAudioEvent::Control(ControlEvent::Raw(1, value.min(127))),
+        ));
+    })
+}
+
+/// Sets how far the pitch wheel bends the notes of a channel of the desired
+/// channel group. This has the same effect as sending the pitch bend
+/// sensitivity RPN (0, 0) followed by the null RPN (127, 127), so it is kept
+/// in saved channel states and reset by MIDI_EVENT_RESETCONTROL. Later data
+/// entry messages do not change the range until an RPN is selected again.
+/// The default range is 2 semitones.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - channel: The number of the MIDI channel (MIDI channel 1 is 0)
+/// - semitones: The pitch bend range in semitones (0-127), with a precision
+///         of one cent
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetPitchBendRange(
+    handle: XSynth_ChannelGroup,
+    channel: u32,
+    semitones: f32,
+) {
+    catch_panic((), || {
+        let cents = (semitones.clamp(0.0, 127.0) * 100.0).round() as u32;
+        let coarse = (cents / 100).min(127) as u8;
+        let fine = if coarse == 127 {
+            0
+        } else {
+            (cents % 100) as u8
+        };
+
+        let instance = handle.as_mut();
+        let events = [
+            (101, 0),
+            (100, 0),
+            (6, coarse),
+            (38, fine),
+            (101, 127),
+            (100, 127),
+        ];
+        for (controller, value) in events {
+            instance.send_event(SynthEvent::Channel(
+                channel,
+                ChannelAudioEvent::Control(ControlEvent::Raw(controller, value)),
+            ));
+        }
+    })
 }
 
 /// Reads audio samples from the desired channel group. The amount of samples
@@ -180,6 +450,10 @@ pub extern "C" fn XSynth_ChannelGroup_SendEvent(
 /// we send a note off event for the same key, then on the next read the key
 /// will be released. If we don't, then the note will keep playing.
 ///
+/// The previous contents of the buffer are always overwritten, even if the
+/// group is silent. To add the audio to the existing contents instead, see
+/// XSynth_ChannelGroup_ReadSamplesAdd.
+///
 /// --Parameters--
 /// - handle: The handle of the channel group instance
 /// - buffer: Pointer to a mutable buffer to receive the audio samples. Each
@@ -192,85 +466,2360 @@ pub unsafe extern "C" fn XSynth_ChannelGroup_ReadSamples(
     buffer: *mut f32,
     length: u64,
 ) {
-    unsafe {
+    catch_panic((), || unsafe {
         if buffer.is_null() {
             return;
         }
 
         let slc = std::slice::from_raw_parts_mut(buffer, length as usize);
         handle.as_mut().read_samples(slc);
-    }
+    })
 }
 
-/// Returns the audio stream parameters of the desired channel group as an
-/// XSynth_StreamParams struct. This may be useful when loading a new soundfont
-/// which is meant to be used in that channel group.
+/// Level statistics of a block of rendered audio, as linear amplitudes
+/// (1.0 = 0dBFS) measured across all audio channels.
+/// - peak: The highest absolute sample value
+/// - rms: The root mean square of the samples
+/// - true_peak: An estimate of the highest level between the samples, which
+///         is at least the sample peak
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct XSynth_AudioStats {
+    pub peak: f32,
+    pub rms: f32,
+    pub true_peak: f32,
+}
+
+/// Reads audio samples from the desired channel group, like
+/// XSynth_ChannelGroup_ReadSamples, and measures their levels in the same
+/// call. This is useful for checking the loudness of exports without
+/// scanning the audio again.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - buffer: Pointer to a mutable buffer to receive the audio samples. Each
+///         item of the buffer should correspond to an audio sample of type
+///         32bit float.
+/// - length: Length of the above buffer, or number of samples to read
+/// - out: Pointer to the XSynth_AudioStats struct to receive the levels of
+///         the rendered samples. Can be null.
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_ChannelGroup_RenderAndAnalyze(
+    handle: XSynth_ChannelGroup,
+    buffer: *mut f32,
+    length: u64,
+    out: *mut XSynth_AudioStats,
+) {
+    catch_panic((), || unsafe {
+        if buffer.is_null() {
+            return;
+        }
+
+        let slc = std::slice::from_raw_parts_mut(buffer, length as usize);
+        let instance = handle.as_mut();
+        instance.read_samples(slc);
+
+        if !out.is_null() {
+            let channels = instance.stream_params().channels.count();
+            let levels = analyze_levels(slc, channels);
+            *out = XSynth_AudioStats {
+                peak: levels.peak,
+                rms: levels.rms,
+                true_peak: levels.true_peak,
+            };
+        }
+    })
+}
+
+/// Reads audio samples from the desired channel group as 64bit floats.
+/// Works like XSynth_ChannelGroup_ReadSamples, but the audio is converted to
+/// double precision after rendering, which may be convenient for further
+/// processing.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - buffer: Pointer to a mutable buffer to receive the audio samples. Each
+///         item of the buffer should correspond to an audio sample of type
+///         64bit float.
+/// - length: Length of the above buffer, or number of samples to read
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_ChannelGroup_ReadSamplesF64(
+    handle: XSynth_ChannelGroup,
+    buffer: *mut f64,
+    length: u64,
+) {
+    catch_panic((), || unsafe {
+        if buffer.is_null() {
+            return;
+        }
+
+        let slc = std::slice::from_raw_parts_mut(buffer, length as usize);
+        let rendered = handle.as_mut().read_scratch(slc.len());
+        for (out, &sample) in slc.iter_mut().zip(rendered) {
+            *out = sample as f64;
+        }
+    })
+}
+
+/// Reads audio samples from the desired channel group in blocks, calling the
+/// given callback before each block is rendered. This allows the host to send
+/// events from the callback with the timing precision of the block size,
+/// while still reading a larger buffer at once.
+///
+/// The callback may send events to the same channel group (for example using
+/// XSynth_ChannelGroup_SendEvent) or change its settings, but it must not read
+/// samples from it, clone it or drop it.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - buffer: Pointer to a mutable buffer to receive the audio samples. Each
+///         item of the buffer should correspond to an audio sample of type
+///         32bit float.
+/// - length: Length of the above buffer, or number of samples to read
+/// - block: The number of samples rendered between calls of the callback. It
+///         is rounded down to a whole number of audio frames.
+/// - callback: Function called before each block with the position of the
+///         block in the buffer (in samples) and the user data pointer
+/// - user: Pointer passed to the callback as is
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_ChannelGroup_RenderInteractive(
+    handle: XSynth_ChannelGroup,
+    buffer: *mut f32,
+    length: u64,
+    block: u64,
+    callback: Option<unsafe extern "C" fn(u64, *mut c_void)>,
+    user: *mut c_void,
+) {
+    catch_panic((), || unsafe {
+        if buffer.is_null() {
+            return;
+        }
+
+        let slc = std::slice::from_raw_parts_mut(buffer, length as usize);
+        let channels = handle.as_ref().stream_params().channels.count() as usize;
+        let block = (block as usize / channels * channels).max(channels);
+
+        for (i, chunk) in slc.chunks_mut(block).enumerate() {
+            if let Some(callback) = callback {
+                callback((i * block) as u64, user);
+            }
+            handle.as_mut().read_samples(chunk);
+        }
+    })
+}
+
+/// Reads audio samples from the desired channel group and adds them to the
+/// existing contents of the buffer. Works like XSynth_ChannelGroup_ReadSamples,
+/// but allows mixing multiple synthesizers into the same buffer without an
+/// intermediate one.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - buffer: Pointer to a mutable buffer holding the audio to add to. Each
+///         item of the buffer should correspond to an audio sample of type
+///         32bit float.
+/// - length: Length of the above buffer, or number of samples to read
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_ChannelGroup_ReadSamplesAdd(
+    handle: XSynth_ChannelGroup,
+    buffer: *mut f32,
+    length: u64,
+) {
+    catch_panic((), || unsafe {
+        if buffer.is_null() {
+            return;
+        }
+
+        let slc = std::slice::from_raw_parts_mut(buffer, length as usize);
+        let rendered = handle.as_mut().read_scratch(slc.len());
+        for (out, &sample) in slc.iter_mut().zip(rendered) {
+            *out += sample;
+        }
+    })
+}
+
+/// Reads audio samples from the desired channel group, converting them to
+/// the requested number of audio channels. Works like
+/// XSynth_ChannelGroup_ReadSamples, but allows a stereo channel group to be
+/// read as mono and vice versa. Stereo audio is downmixed to mono by summing
+/// both channels at -3dB, while mono audio is duplicated to both channels.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - buffer: Pointer to a mutable buffer to receive the audio samples. Each
+///         item of the buffer should correspond to an audio sample of type
+///         32bit float.
+/// - length: Length of the above buffer, or number of samples to read
+/// - out_channels: Number of audio channels of the buffer
+///         Supported: AUDIO_CHANNELS_MONO (mono), AUDIO_CHANNELS_STEREO (stereo)
+///
+/// --Returns--
+/// This function returns true if the samples were read. If out_channels is
+/// not supported, nothing is read, false is returned and the reason can be
+/// retrieved using XSynth_GetLastError.
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_ChannelGroup_ReadSamplesAs(
+    handle: XSynth_ChannelGroup,
+    buffer: *mut f32,
+    length: u64,
+    out_channels: u16,
+) -> bool {
+    catch_panic(false, || unsafe {
+        if !matches!(out_channels, AUDIO_CHANNELS_MONO | AUDIO_CHANNELS_STEREO) {
+            set_last_error(&format!(
+                "Unsupported number of audio channels: {out_channels}"
+            ));
+            return false;
+        }
+        if buffer.is_null() {
+            return false;
+        }
+
+        let slc = std::slice::from_raw_parts_mut(buffer, length as usize);
+        let instance = handle.as_mut();
+        let in_channels = instance.stream_params().channels.count();
+        if in_channels == out_channels {
+            instance.read_samples(slc);
+            return true;
+        }
+
+        let frames = slc.len() / out_channels as usize;
+        let rendered = instance.read_scratch(frames * in_channels as usize);
+        remix_channels(rendered, in_channels, slc, out_channels);
+        true
+    })
+}
+
+/// Renders the remaining audio of the desired channel group until all of its
+/// voices have decayed, passing each rendered chunk to the given callback.
+/// This is meant to be called at the end of an export, after the last MIDI
+/// event has been sent, so the release tails of the notes are not cut off.
+///
+/// Voices that never decay, such as held notes or looped samples with the
+/// sustain pedal down, would keep this function rendering forever. Use the
+/// max_samples and silence_threshold parameters to bound it.
 ///
 /// --Parameters--
 /// - handle: The handle of the channel group instance
+/// - scratch: Pointer to a mutable buffer used for rendering each chunk
+/// - scratch_len: Length of the above buffer, or number of samples per chunk
+/// - max_samples: The maximum number of samples to render, counting all audio
+///         channels (0 = no limit). The last chunk is shortened to fit.
+/// - silence_threshold: Stops rendering after a chunk whose samples are all
+///         quieter than this amplitude, even if voices are still active
+///         (0 = disabled). For example, 0.0001 is -80dBFS.
+/// - callback: Function called for every rendered chunk with a pointer to the
+///         samples, the number of samples and the user data pointer
+/// - user: Pointer passed to the callback as is
 ///
 /// --Returns--
-/// This function returns an XSynth_StreamParams struct.
+/// The number of samples rendered and passed to the callback.
 #[no_mangle]
-pub extern "C" fn XSynth_ChannelGroup_GetStreamParams(
+pub unsafe extern "C" fn XSynth_ChannelGroup_RenderTail(
     handle: XSynth_ChannelGroup,
-) -> XSynth_StreamParams {
-    convert_streamparams_to_c(handle.as_ref().stream_params())
+    scratch: *mut f32,
+    scratch_len: u64,
+    max_samples: u64,
+    silence_threshold: f32,
+    callback: Option<unsafe extern "C" fn(*const f32, u64, *mut c_void)>,
+    user: *mut c_void,
+) -> u64 {
+    catch_panic(0, || unsafe {
+        let Some(callback) = callback else {
+            return 0;
+        };
+        if scratch.is_null() || scratch_len == 0 {
+            return 0;
+        }
+
+        let slc = std::slice::from_raw_parts_mut(scratch, scratch_len as usize);
+        let instance = handle.as_mut();
+        let mut rendered = 0;
+        while instance.group.voice_count() > 0 {
+            let len = match max_samples {
+                0 => scratch_len,
+                max if rendered < max => scratch_len.min(max - rendered),
+                _ => break,
+            };
+            let chunk = &mut slc[..len as usize];
+            instance.read_samples(chunk);
+            callback(chunk.as_ptr(), len, user);
+            rendered += len;
+
+            if silence_threshold > 0.0 && chunk.iter().all(|s| s.abs() < silence_threshold) {
+                break;
+            }
+        }
+        rendered
+    })
 }
 
-/// Sets the given layer limit for the desired channel group. One layer
-/// corresponds to one voice per key per channel.
+/// Preallocates the internal buffers of the desired channel group used when
+/// reading the given number of samples, so that later reads of up to that
+/// length do not allocate memory. This applies to
+/// XSynth_ChannelGroup_ReadSamplesF64, XSynth_ChannelGroup_ReadSamplesAs,
+/// XSynth_ChannelGroup_ReadSamplesAdd and XSynth_ChannelGroup_FillRing, which
+/// is useful for realtime or tight export loops where allocations in the
+/// render path should be avoided.
+///
+/// The buffers of the XSynth renderer itself are kept between reads, so they
+/// only grow during the first read of a given length.
 ///
 /// --Parameters--
 /// - handle: The handle of the channel group instance
-/// - layers: The layer limit (0 = no limit, 1-MAX = limit)
-///         Where MAX is the maximum value of an unsigned 64bit integer
+/// - length: The largest number of samples that will be read at once
 #[no_mangle]
-pub extern "C" fn XSynth_ChannelGroup_SetLayerCount(handle: XSynth_ChannelGroup, layers: u64) {
-    let layercount = convert_layer_count(layers);
-    handle.as_mut().send_event(SynthEvent::ChannelConfig(
-        ChannelConfigEvent::SetLayerCount(layercount),
-    ));
+pub extern "C" fn XSynth_ChannelGroup_PrepareForLength(handle: XSynth_ChannelGroup, length: u64) {
+    catch_panic((), || {
+        handle.as_mut().prepare_for_length(length as usize);
+    })
 }
 
-/// Sets a list of soundfonts to be used in the desired channel group. To load
-/// a new soundfont, see the XSynth_Soundfont_LoadNew function.
+/// Returns the keys that are currently held on a channel of the desired
+/// channel group, which is useful for driving a live keyboard display. This is
+/// a snapshot of the note on and note off events sent to the group, so keys
+/// stop being reported once they are released, even if their release is still
+/// audible.
 ///
 /// --Parameters--
 /// - handle: The handle of the channel group instance
-/// - sf_ids: Pointer to an array of soundfont handles
-/// - count: The length of the above array
+/// - channel: The number of the MIDI channel (MIDI channel 1 is 0)
+/// - out: Pointer to a mutable buffer to receive the held key numbers, in
+///         ascending order
+/// - cap: Length of the above buffer
+///
+/// --Returns--
+/// The number of held keys. If it is larger than cap, only the first cap keys
+/// are written to the buffer.
 #[no_mangle]
-pub unsafe extern "C" fn XSynth_ChannelGroup_SetSoundfonts(
+pub unsafe extern "C" fn XSynth_ChannelGroup_GetActiveKeys(
     handle: XSynth_ChannelGroup,
-    sf_ids: *const XSynth_Soundfont,
-    count: u64,
-) {
-    unsafe {
-        let ids = std::slice::from_raw_parts(sf_ids, count as usize);
-        let sfvec = sfids_to_vec(ids);
-        handle.as_mut().send_event(SynthEvent::ChannelConfig(
-            ChannelConfigEvent::SetSoundfonts(sfvec),
-        ));
-    }
+    channel: u32,
+    out: *mut u8,
+    cap: u64,
+) -> u64 {
+    catch_panic(0, || unsafe {
+        let Some(keys) = handle.as_ref().active_keys.get(channel as usize) else {
+            return 0;
+        };
+
+        let active = (0..128u8).filter(|&key| keys[key as usize]);
+        let mut count = 0;
+        for key in active {
+            if !out.is_null() && count < cap {
+                *out.add(count as usize) = key;
+            }
+            count += 1;
+        }
+        count
+    })
 }
 
-/// Removes all the soundfonts used in the desired channel group.
+/// Returns the last value of a controller (CC) received on a channel of the
+/// desired channel group, which allows saving the complete controller state
+/// of a session. Values are forgotten when a MIDI_EVENT_RESETCONTROL event is
+/// received on the channel or the group is reset.
 ///
 /// --Parameters--
 /// - handle: The handle of the channel group instance
+/// - channel: The number of the MIDI channel (MIDI channel 1 is 0)
+/// - controller: The controller number (0-127)
+///
+/// --Returns--
+/// The last value (0-127) of the controller, or -1 if it was never set or the
+/// channel or controller does not exist.
 #[no_mangle]
-pub extern "C" fn XSynth_ChannelGroup_ClearSoundfonts(handle: XSynth_ChannelGroup) {
-    handle.as_mut().send_event(SynthEvent::ChannelConfig(
-        ChannelConfigEvent::SetSoundfonts(Vec::new()),
-    ));
+pub extern "C" fn XSynth_ChannelGroup_GetControlValue(
+    handle: XSynth_ChannelGroup,
+    channel: u32,
+    controller: u8,
+) -> i16 {
+    catch_panic(-1, || {
+        handle
+            .as_ref()
+            .channel_states
+            .get(channel as usize)
+            .and_then(|state| state.controls.get(controller as usize).copied().flatten())
+            .map_or(-1, |value| value as i16)
+    })
 }
 
-/// Drops the desired channel group.
+/// Returns the peak levels of the output of the desired channel group since
+/// the last call of this function. The levels fall off at 20dB per second, so
+/// short peaks remain visible for a while, which is useful for driving a
+/// level meter.
 ///
 /// --Parameters--
 /// - handle: The handle of the channel group instance
+/// - left: Pointer to receive the peak level of the left (or mono) channel
+/// - right: Pointer to receive the peak level of the right channel. This is
+///         not written to for mono channel groups.
 #[no_mangle]
-pub extern "C" fn XSynth_ChannelGroup_Drop(handle: XSynth_ChannelGroup) {
-    handle.drop();
+pub unsafe extern "C" fn XSynth_ChannelGroup_GetPeakLevel(
+    handle: XSynth_ChannelGroup,
+    left: *mut f32,
+    right: *mut f32,
+) {
+    catch_panic((), || unsafe {
+        let instance = handle.as_mut();
+        let params = instance.stream_params();
+        let peaks = instance.effects.meter.take(params.sample_rate);
+
+        if !left.is_null() {
+            *left = peaks[0];
+        }
+        if !right.is_null() && params.channels.count() > 1 {
+            *right = peaks[1];
+        }
+    })
+}
+
+/// Returns the correlation between the left and right channels of the output
+/// of the desired channel group since the last call of this function. This
+/// shows how well the audio folds down to mono: values near 1 mean the
+/// channels are nearly identical, values near 0 mean they are unrelated, and
+/// negative values mean they cancel out when mixed to mono.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+///
+/// --Returns--
+/// The correlation, from -1.0 to 1.0. Silence is reported as 0.0, and mono
+/// channel groups always report 1.0.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_GetMonoCompatibility(handle: XSynth_ChannelGroup) -> f32 {
+    catch_panic(0.0, || {
+        let instance = handle.as_mut();
+        if instance.stream_params().channels.count() < 2 {
+            return 1.0;
+        }
+        instance.effects.correlation.take()
+    })
+}
+
+/// Creates a lock-free ring buffer for the desired channel group, which
+/// allows the audio to be rendered on one thread and consumed on another
+/// without locking. The group renders into the buffer when
+/// XSynth_ChannelGroup_FillRing is called, and the samples can be read using
+/// XSynth_RingBuffer_Read.
+///
+/// Only one thread may fill the ring buffer and only one thread may read from
+/// it at any given time. Each channel group has a single ring buffer, so
+/// calling this function again replaces the previous one.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - capacity: The number of audio samples the ring buffer can hold
+///
+/// --Returns--
+/// This function returns the handle of the ring buffer, which has to be freed
+/// using XSynth_RingBuffer_Drop.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_CreateRingBuffer(
+    handle: XSynth_ChannelGroup,
+    capacity: u64,
+) -> XSynth_RingBuffer {
+    catch_panic(XSynth_RingBuffer::null(), || {
+        let ring = Arc::new(RingBuffer::new(capacity as usize));
+        handle.as_mut().ring = Some(ring.clone());
+        XSynth_RingBuffer::from(ring)
+    })
+}
+
+/// Renders audio from the desired channel group into its ring buffer, until
+/// the ring buffer is full.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+///
+/// --Returns--
+/// The number of samples written to the ring buffer. If the group has no ring
+/// buffer, 0 is returned.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_FillRing(handle: XSynth_ChannelGroup) -> u64 {
+    catch_panic(0, || handle.as_mut().fill_ring() as u64)
+}
+
+/// Reads audio samples from a ring buffer created using
+/// XSynth_ChannelGroup_CreateRingBuffer.
+///
+/// --Parameters--
+/// - ring: The handle of the ring buffer
+/// - buffer: Pointer to a mutable buffer to receive the audio samples
+/// - length: Length of the above buffer, or number of samples to read
+///
+/// --Returns--
+/// The number of samples read, which is smaller than length if the ring
+/// buffer did not contain enough samples.
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_RingBuffer_Read(
+    ring: XSynth_RingBuffer,
+    buffer: *mut f32,
+    length: u64,
+) -> u64 {
+    catch_panic(0, || unsafe {
+        if buffer.is_null() {
+            return 0;
+        }
+
+        let slc = std::slice::from_raw_parts_mut(buffer, length as usize);
+        ring.as_ref().pop(slc) as u64
+    })
+}
+
+/// Frees the handle of a ring buffer. If the ring buffer still belongs to a
+/// channel group, it is freed once the group is dropped or gets a new one.
+///
+/// --Parameters--
+/// - ring: The handle of the ring buffer
+#[no_mangle]
+pub extern "C" fn XSynth_RingBuffer_Drop(ring: XSynth_RingBuffer) {
+    catch_panic((), || {
+        ring.drop();
+    })
+}
+
+/// Returns the audio stream parameters of the desired channel group as an
+/// XSynth_StreamParams struct. This may be useful when loading a new soundfont
+/// which is meant to be used in that channel group.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+///
+/// --Returns--
+/// This function returns an XSynth_StreamParams struct.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_GetStreamParams(
+    handle: XSynth_ChannelGroup,
+) -> XSynth_StreamParams {
+    catch_panic(
+        XSynth_StreamParams {
+            sample_rate: 0,
+            audio_channels: 0,
+        },
+        || convert_streamparams_to_c(&handle.as_ref().stream_params()),
+    )
+}
+
+/// Returns the audio sample rate of the desired channel group.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+///
+/// --Returns--
+/// The sample rate in Hz
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_GetSampleRate(handle: XSynth_ChannelGroup) -> u32 {
+    catch_panic(0, || handle.as_ref().stream_params().sample_rate)
+}
+
+/// Returns the number of audio channels of the desired channel group.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+///
+/// --Returns--
+/// The number of audio channels: AUDIO_CHANNELS_MONO (mono) or
+/// AUDIO_CHANNELS_STEREO (stereo)
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_GetChannelCount(handle: XSynth_ChannelGroup) -> u16 {
+    catch_panic(0, || handle.as_ref().stream_params().channels.count())
+}
+
+/// Changes the audio stream parameters of the desired channel group.
+///
+/// The channel group is rebuilt with the new parameters, so all active notes
+/// are stopped. The soundfonts, layer limit and control change data of the
+/// group are kept. Since soundfonts are resampled to the output sample rate
+/// while loading, any soundfont of the group that was loaded with different
+/// parameters is loaded again, which can take a while. The soundfont handles
+/// stay the same, so changing their settings or reloading them still affects
+/// this group.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - params: The new audio stream parameters (see XSynth_StreamParams)
+///
+/// --Returns--
+/// RESULT_OK if the group was reconfigured successfully, or
+/// RESULT_ERROR_SOUNDFONT_LOAD if one of its soundfonts could not be loaded
+/// again. In that case the group is left unchanged.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetStreamParams(
+    handle: XSynth_ChannelGroup,
+    params: XSynth_StreamParams,
+) -> i32 {
+    catch_panic(RESULT_ERROR_PANIC, || {
+        if handle.as_mut().set_stream_params(params) {
+            RESULT_OK
+        } else {
+            RESULT_ERROR_SOUNDFONT_LOAD
+        }
+    })
+}
+
+/// Sets the oversampling factor of the desired channel group, to reduce
+/// aliasing in high quality offline renders. The group renders at the given
+/// multiple of its sample rate, and filters the audio down to the output
+/// sample rate on every read. The output stream parameters of the group are
+/// not changed.
+///
+/// This multiplies the CPU cost of rendering by about the factor, plus the
+/// cost of the filter. The soundfonts of the group are also loaded again at
+/// the higher rate, which takes a while and uses the same multiple of memory.
+/// It is meant for offline rendering, so realtime use should keep it at 1.
+///
+/// Like XSynth_ChannelGroup_SetStreamParams, this rebuilds the channel group,
+/// so all active notes are stopped.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - factor: The oversampling factor: 1 (disabled, the default), 2 or 4
+///
+/// --Returns--
+/// True if the oversampling was set, or false if the factor is not supported
+/// or one of the soundfonts of the group could not be loaded at the higher
+/// rate. In that case the group is left unchanged, and the reason can be
+/// retrieved using XSynth_GetLastError.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetOversampling(
+    handle: XSynth_ChannelGroup,
+    factor: u16,
+) -> bool {
+    catch_panic(false, || {
+        if !matches!(factor, 1 | 2 | 4) {
+            set_last_error("The oversampling factor has to be 1, 2 or 4");
+            return false;
+        }
+        if !handle.as_mut().set_oversampling(factor) {
+            set_last_error("A soundfont could not be loaded at the oversampled rate");
+            return false;
+        }
+        true
+    })
+}
+
+/// Returns the oversampling factor of the desired channel group, as set using
+/// XSynth_ChannelGroup_SetOversampling.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+///
+/// --Returns--
+/// The oversampling factor, which is 1 if oversampling is disabled.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_GetOversampling(handle: XSynth_ChannelGroup) -> u16 {
+    catch_panic(0, || handle.as_ref().oversampling)
+}
+
+/// Sets the given layer limit for the desired channel group. One layer
+/// corresponds to one voice per key per channel.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - layers: The layer limit (0 = no limit, 1-MAX = limit)
+///         Where MAX is the maximum value of an unsigned 64bit integer
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetLayerCount(handle: XSynth_ChannelGroup, layers: u64) {
+    catch_panic((), || {
+        let layercount = convert_layer_count(layers);
+        handle.as_mut().set_layer_count(layercount);
+    })
+}
+
+/// Returns the layer limit of the desired channel group, as set using
+/// XSynth_ChannelGroup_SetLayerCount.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+///
+/// --Returns--
+/// The layer limit (0 = no limit). If the limit was never set, 0 is returned
+/// and the default limit of XSynth applies.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_GetLayerCount(handle: XSynth_ChannelGroup) -> u64 {
+    catch_panic(0, || {
+        handle.as_ref().layers.map_or(0, |layers| layers as u64)
+    })
+}
+
+/// Enables or disables deterministic rendering for the desired channel group,
+/// so that rendering the same events twice produces bit-exact identical
+/// audio. This is useful for regression testing of exports.
+///
+/// Deterministic rendering disables the threadpool for this group, so all
+/// channels are rendered on the calling thread. Changing this setting rebuilds
+/// the group, which stops all active notes. The soundfonts, layer limit and
+/// controller state of the group are kept.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - deterministic: Whether the group should render deterministically
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetDeterministic(
+    handle: XSynth_ChannelGroup,
+    deterministic: bool,
+) {
+    catch_panic((), || {
+        handle.as_mut().set_use_threadpool(!deterministic);
+    })
+}
+
+/// Sets the stereo width of the output of the desired channel group. This
+/// is only applied to groups with stereo audio output.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - width: The stereo width. 1.0 leaves the audio unchanged, 0.0 collapses
+///         it to mono and values above 1.0 widen it.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetStereoWidth(handle: XSynth_ChannelGroup, width: f32) {
+    catch_panic((), || {
+        handle.as_mut().effects.stereo_width = width.max(0.0);
+    })
+}
+
+/// Swaps the left and right channels of the output of the desired channel
+/// group. This is only applied to groups with stereo audio output.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - swapped: Whether the left and right channels should be swapped
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetChannelSwap(handle: XSynth_ChannelGroup, swapped: bool) {
+    catch_panic((), || {
+        handle.as_mut().effects.swap_channels = swapped;
+    })
+}
+
+/// Enables or disables the DC offset filter on the output of the desired
+/// channel group. The filter is a gentle 5Hz high-pass filter, which removes
+/// the DC offset some soundfonts have without audibly changing the sound. It
+/// is disabled by default.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - enabled: Whether the DC offset filter should be applied
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetDCFilter(handle: XSynth_ChannelGroup, enabled: bool) {
+    catch_panic((), || {
+        let effects = &mut handle.as_mut().effects;
+        if !enabled {
+            effects.dc_filter = None;
+        } else if effects.dc_filter.is_none() {
+            effects.dc_filter = Some(DcFilter::new());
+        }
+    })
+}
+
+/// Fades out the next samples of the output of the desired channel group
+/// linearly to silence, which gives exports a smooth ending when they are
+/// stopped before the notes have decayed. The fade only applies once; the
+/// samples read after it are not affected.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - fade_samples: The length of the fade in samples, counting all audio
+///         channels like the length given to XSynth_ChannelGroup_ReadSamples
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_ApplyFadeOut(handle: XSynth_ChannelGroup, fade_samples: u64) {
+    catch_panic((), || {
+        let instance = handle.as_mut();
+        let channels = instance.stream_params().channels.count() as u64;
+        let frames = fade_samples / channels.max(1);
+        instance.effects.fade_out = (frames > 0).then(|| FadeOut::new(frames));
+    })
+}
+
+/// Sets up a compressor on the output of the desired channel group. The
+/// compressor has a soft knee of 6dB and reacts to the loudest of the audio
+/// channels. It is disabled by default.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - enabled: Whether or not to use the compressor
+/// - threshold: The level above which the audio is compressed, in dBFS
+/// - ratio: The compression ratio (for example 4.0 for 4:1). Values below
+///         1.0 are treated as 1.0.
+/// - attack_ms: The attack time in milliseconds
+/// - release_ms: The release time in milliseconds
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetCompressor(
+    handle: XSynth_ChannelGroup,
+    enabled: bool,
+    threshold: f32,
+    ratio: f32,
+    attack_ms: f32,
+    release_ms: f32,
+) {
+    catch_panic((), || {
+        handle.as_mut().effects.compressor = if enabled {
+            Some(Compressor::new(threshold, ratio, attack_ms, release_ms))
+        } else {
+            None
+        };
+    })
+}
+
+/// Sets how the desired channel group handles output samples outside of the
+/// [-1.0, 1.0] range. The peak level meter is read before this is applied, so
+/// it still reports these samples.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - mode: How the samples outside of the range are handled
+///         - OUTPUT_CLAMP_NONE: They are passed through unchanged, for further
+///           processing by the caller (default)
+///         - OUTPUT_CLAMP_HARD: They are clipped to the range, which keeps
+///           the rest of the audio unchanged but distorts audibly
+///         - OUTPUT_CLAMP_TANH: All samples are soft clipped using tanh,
+///           which distorts more gently but also slightly compresses loud
+///           audio within the range
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetOutputClamp(handle: XSynth_ChannelGroup, mode: u16) {
+    catch_panic((), || {
+        handle.as_mut().effects.output_clamp = match mode {
+            OUTPUT_CLAMP_HARD | OUTPUT_CLAMP_TANH => mode,
+            _ => OUTPUT_CLAMP_NONE,
+        };
+    })
+}
+
+/// The current configuration of the output effects of a channel group
+/// - struct_size: The size of the struct in bytes, which has to be set to
+///         sizeof(XSynth_EffectsState) by the caller. Fields added in later
+///         versions are only written if they fit in this size.
+/// - dc_filter_enabled: Whether the DC offset filter is applied
+/// - stereo_width: The stereo width (see XSynth_ChannelGroup_SetStereoWidth)
+/// - channels_swapped: Whether the left and right channels are swapped
+/// - compressor_enabled: Whether the compressor is applied
+/// - compressor_threshold: The threshold of the compressor in dBFS, or 0.0
+///         if it is disabled
+/// - compressor_ratio: The ratio of the compressor, or 1.0 if it is disabled
+/// - fading_out: Whether a fade out started by
+///         XSynth_ChannelGroup_ApplyFadeOut is in progress
+/// - filter_enabled: Whether any soundfont of the group was loaded with
+///         use_effects, which applies its low-pass filters to the voices
+/// - master_gain: The linear gain of the output. Channel groups have no
+///         master gain control yet, so this is always 1.0
+/// - limiter_enabled: Whether a limiter is applied. Channel groups have no
+///         limiter yet, so this is always false
+/// - limiter_ceiling: The ceiling of the limiter in dBFS, or 0.0 if it is
+///         disabled
+/// - output_clamp: The OUTPUT_CLAMP_* mode (see
+///         XSynth_ChannelGroup_SetOutputClamp)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct XSynth_EffectsState {
+    pub struct_size: u32,
+    pub dc_filter_enabled: bool,
+    pub stereo_width: f32,
+    pub channels_swapped: bool,
+    pub compressor_enabled: bool,
+    pub compressor_threshold: f32,
+    pub compressor_ratio: f32,
+    pub fading_out: bool,
+    pub filter_enabled: bool,
+    pub master_gain: f32,
+    pub limiter_enabled: bool,
+    pub limiter_ceiling: f32,
+    pub output_clamp: u16,
+}
+
+/// Reads the current configuration of the output effects of the desired
+/// channel group, so that all of it can be displayed at once. Only the fields
+/// that fit in the struct_size set by the caller are written, so callers
+/// compiled against an older, smaller version of the struct are supported.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - out: Pointer to the XSynth_EffectsState struct to be filled, with its
+///         struct_size field set
+///
+/// --Returns--
+/// This function returns true if the struct was filled, or false if the given
+/// pointer is null or the struct_size field is too small. The struct_size
+/// field is set to the number of bytes written.
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_ChannelGroup_GetEffectsState(
+    handle: XSynth_ChannelGroup,
+    out: *mut XSynth_EffectsState,
+) -> bool {
+    catch_panic(false, || unsafe {
+        if out.is_null() {
+            return false;
+        }
+        let struct_size = std::ptr::read_unaligned(out as *const u32);
+        if (struct_size as usize) < std::mem::size_of::<u32>() {
+            set_last_error("The struct_size field of XSynth_EffectsState is not set");
+            return false;
+        }
+
+        let instance = handle.as_ref();
+        let effects = &instance.effects;
+        let compressor = effects.compressor.as_ref();
+        let size = std::mem::size_of::<XSynth_EffectsState>().min(struct_size as usize);
+        let state = XSynth_EffectsState {
+            struct_size: size as u32,
+            dc_filter_enabled: effects.dc_filter.is_some(),
+            stereo_width: effects.stereo_width,
+            channels_swapped: effects.swap_channels,
+            compressor_enabled: compressor.is_some(),
+            compressor_threshold: compressor.map_or(0.0, |c| c.threshold),
+            compressor_ratio: compressor.map_or(1.0, |c| c.ratio),
+            fading_out: effects.fade_out.is_some(),
+            filter_enabled: instance.soundfonts.iter().any(|sf| sf.options.use_effects),
+            master_gain: 1.0,
+            limiter_enabled: false,
+            limiter_ceiling: 0.0,
+            output_clamp: effects.output_clamp,
+        };
+
+        std::ptr::copy_nonoverlapping(
+            &state as *const XSynth_EffectsState as *const u8,
+            out as *mut u8,
+            size,
+        );
+        true
+    })
+}
+
+/// Assigns a key of a channel to a choke group in the desired channel group.
+/// When a note on event is received for a key in a choke group, all other
+/// keys of the same channel in that group are released. This is commonly
+/// used for drum kits, for example for open and closed hi-hats.
+///
+/// Exclusive classes defined in the soundfont are not read by XSynth, so
+/// they have to be set up manually using this function.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - channel: The number of the MIDI channel (MIDI channel 1 is 0)
+/// - key: The key number (0-127)
+/// - group_id: The ID of the choke group. A value of 0 removes the key from
+///         any choke group.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetChokeGroup(
+    handle: XSynth_ChannelGroup,
+    channel: u32,
+    key: u8,
+    group_id: u32,
+) {
+    catch_panic((), || {
+        let choke_groups = &mut handle.as_mut().choke_groups;
+        if group_id == 0 {
+            choke_groups.remove(&(channel, key));
+        } else {
+            choke_groups.insert((channel, key), group_id);
+        }
+    })
+}
+
+/// Sets a velocity curve for the desired channel group, which remaps the
+/// velocity of every incoming note on event. Notes whose velocity is mapped
+/// to 0 are dropped.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - curve: Pointer to an array of 128 velocities (0-127), where the item at
+///         each index is the output velocity for that input velocity. A null
+///         pointer resets the curve to linear (velocities are left unchanged).
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_ChannelGroup_SetVelocityCurve(
+    handle: XSynth_ChannelGroup,
+    curve: *const u8,
+) {
+    catch_panic((), || unsafe {
+        handle.as_mut().velocity_curve = if curve.is_null() {
+            None
+        } else {
+            let mut table = [0; 128];
+            table.copy_from_slice(std::slice::from_raw_parts(curve, 128));
+            Some(table)
+        };
+    })
+}
+
+/// Limits the keys that can be played on a channel of the desired channel
+/// group. Note on events for keys outside of the range are ignored.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - channel: The number of the MIDI channel (MIDI channel 1 is 0)
+/// - low: The lowest playable key (0-127)
+/// - high: The highest playable key (low-127)
+///         A range of 0-127 disables the filtering.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetKeyRange(
+    handle: XSynth_ChannelGroup,
+    channel: u32,
+    low: u8,
+    high: u8,
+) {
+    catch_panic((), || {
+        let key_ranges = &mut handle.as_mut().key_ranges;
+        if low == 0 && high >= 127 {
+            key_ranges.remove(&channel);
+        } else {
+            key_ranges.insert(channel, low..=high);
+        }
+    })
+}
+
+/// Shifts the pitch of all notes played on the desired channel group by the
+/// given amount of cents, which is useful for matching alternative concert
+/// pitches. It is applied on top of the fine tuning of each channel set using
+/// MIDI_EVENT_FINETUNE. For example, about -31.77 cents tunes A4 from 440Hz
+/// to 432Hz.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - cents: The pitch shift in cents (0 = no shift, the default)
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetMasterTuning(handle: XSynth_ChannelGroup, cents: f32) {
+    catch_panic((), || {
+        handle.as_mut().set_master_tuning(cents);
+    })
+}
+
+/// Shifts the keys of all notes played on the desired channel group by the
+/// given number of semitones. Drum channels are not transposed. Notes that
+/// would end up outside of the MIDI key range (0-127) are dropped. Notes that
+/// are held while the transpose changes are still released correctly.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - semitones: The number of semitones to shift by (0 = no transpose)
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetGlobalTranspose(
+    handle: XSynth_ChannelGroup,
+    semitones: i32,
+) {
+    catch_panic((), || {
+        handle.as_mut().transpose = semitones;
+    })
+}
+
+/// Snaps the keys of all notes played on the desired channel group to the
+/// nearest key of the given scale. If two scale keys are equally close, the
+/// lower one is used. The global transpose is applied before snapping, and
+/// drum channels are not affected. It is disabled by default.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - root: The root key of the scale (0-11, 0 = C)
+/// - scale_mask12: Array of 12 booleans, telling whether each semitone above
+///         the root is part of the scale. Ignored when disabling.
+/// - enabled: Whether notes should be snapped to the scale
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_ChannelGroup_SetScaleQuantize(
+    handle: XSynth_ChannelGroup,
+    root: u8,
+    scale_mask12: *const bool,
+    enabled: bool,
+) {
+    catch_panic((), || unsafe {
+        let instance = handle.as_mut();
+        if !enabled || scale_mask12.is_null() {
+            instance.scale_quantize = None;
+            return;
+        }
+
+        let mut scale = [false; 12];
+        scale.copy_from_slice(std::slice::from_raw_parts(scale_mask12, 12));
+        instance.scale_quantize = Some((root % 12, scale));
+    })
+}
+
+/// Limits the number of notes that can be held at the same time on a channel
+/// of the desired channel group. When a new note exceeds the limit, the oldest
+/// notes of the channel are released. This is useful for taming a single
+/// dense track without affecting the others.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - channel: The number of the MIDI channel (MIDI channel 1 is 0)
+/// - limit: The maximum number of held notes (0 = no limit)
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetNoteLimitPerChannel(
+    handle: XSynth_ChannelGroup,
+    channel: u32,
+    limit: u32,
+) {
+    catch_panic((), || {
+        let note_limits = &mut handle.as_mut().note_limits;
+        if limit == 0 {
+            note_limits.remove(&channel);
+        } else {
+            note_limits.insert(channel, limit);
+        }
+    })
+}
+
+/// Limits the number of voices the desired channel group plays at the same
+/// time, trading polyphony for a bounded CPU load. Every voice counts,
+/// including the release tails of released notes and each zone of notes
+/// with multiple zones. When a new voice would exceed the limit, the oldest
+/// voices are killed to make room, released ones first.
+///
+/// If the group was created with fade_out_killing, the killed voices fade out
+/// over a few milliseconds, so the voice count can exceed the limit during
+/// the fade. Otherwise they stop at once.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - max: The maximum number of voices (0 = no limit, the default)
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetMaxVoices(handle: XSynth_ChannelGroup, max: u64) {
+    catch_panic((), || {
+        handle.as_ref().voice_limit.set_max(max);
+    })
+}
+
+/// Returns the voice limit of the desired channel group, as set using
+/// XSynth_ChannelGroup_SetMaxVoices. The group never plays more voices than
+/// this, apart from voices fading out after being killed when the group uses
+/// fade_out_killing.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+///
+/// --Returns--
+/// The maximum number of voices, or 0 if there is no limit.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_GetMaxVoices(handle: XSynth_ChannelGroup) -> u64 {
+    catch_panic(0, || handle.as_ref().voice_limit.max())
+}
+
+/// Sets the maximum duration of notes on the desired channel group. Notes
+/// held for longer are released automatically, which prevents notes from
+/// droning forever when a MIDI is missing note off events. The limit is
+/// checked at the start of every read, so notes are released with the
+/// precision of the read length. It is disabled by default.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - seconds: The maximum note duration in seconds (0 = unlimited)
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetMaxNoteDuration(
+    handle: XSynth_ChannelGroup,
+    seconds: f64,
+) {
+    catch_panic((), || {
+        handle.as_mut().max_note_duration = seconds.max(0.0);
+    })
+}
+
+/// Enables or disables a channel of the desired channel group. A disabled
+/// channel ignores all events sent to it, so it does not use any voices or
+/// processing time. Unlike muting, its controller state is not updated
+/// either. Disabling a channel kills its active notes. This is useful for
+/// skipping entire tracks of a MIDI for better performance.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - channel: The number of the MIDI channel (MIDI channel 1 is 0)
+/// - enabled: Whether the channel should be enabled
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetChannelEnabled(
+    handle: XSynth_ChannelGroup,
+    channel: u32,
+    enabled: bool,
+) {
+    catch_panic((), || {
+        handle.as_mut().set_channel_enabled(channel, enabled);
+    })
+}
+
+/// Returns the drum channels of the desired channel group.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - out: Pointer to a mutable buffer to receive the IDs of the drum channels
+/// - cap: Length of the above buffer
+///
+/// --Returns--
+/// The number of drum channels. If it is larger than cap, only the first cap
+/// channels are written to the buffer.
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_ChannelGroup_GetDrumChannels(
+    handle: XSynth_ChannelGroup,
+    out: *mut u32,
+    cap: u64,
+) -> u64 {
+    catch_panic(0, || unsafe {
+        let drums = &handle.as_ref().settings.drums_channels;
+        if !out.is_null() {
+            for (i, &channel) in drums.iter().take(cap as usize).enumerate() {
+                *out.add(i) = channel;
+            }
+        }
+        drums.len() as u64
+    })
+}
+
+/// Mutes or unmutes all drum channels of the desired channel group at once,
+/// which is useful for previewing only the melodic content of a MIDI. The
+/// drum channels are the ones given in drum_channels when the group was
+/// created. Muting stops the active notes of those channels and ignores new
+/// ones until they are unmuted.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - muted: Whether the drum channels should be muted
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SetDrumsMuted(handle: XSynth_ChannelGroup, muted: bool) {
+    catch_panic((), || {
+        let instance = handle.as_mut();
+        for channel in instance.settings.drums_channels.clone() {
+            instance.set_channel_muted(channel, muted);
+        }
+    })
+}
+
+/// Sets a list of soundfonts to be used in the desired channel group. To load
+/// a new soundfont, see the XSynth_Soundfont_LoadNew function.
+///
+/// The order of the array decides which soundfont is used when more than one
+/// of them contains the requested preset: the first soundfont in the array
+/// that contains it is used.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - sf_ids: Pointer to an array of soundfont handles
+/// - count: The length of the above array
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_ChannelGroup_SetSoundfonts(
+    handle: XSynth_ChannelGroup,
+    sf_ids: *const XSynth_Soundfont,
+    count: u64,
+) {
+    catch_panic((), || unsafe {
+        let ids = std::slice::from_raw_parts(sf_ids, count as usize);
+        let sfvec = sfids_to_vec(ids);
+        handle.as_mut().set_soundfonts(sfvec);
+    })
+}
+
+/// Sets a list of soundfonts to be used in the desired channel group, with
+/// an explicit priority for each of them. When more than one soundfont
+/// contains the requested preset, the one with the highest priority is used.
+/// Soundfonts with the same priority keep their order from the array, as in
+/// XSynth_ChannelGroup_SetSoundfonts.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - sf_ids: Pointer to an array of soundfont handles
+/// - priorities: Pointer to an array with the priority of each soundfont
+/// - count: The length of the above arrays
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_ChannelGroup_SetSoundfontsOrdered(
+    handle: XSynth_ChannelGroup,
+    sf_ids: *const XSynth_Soundfont,
+    priorities: *const u32,
+    count: u64,
+) {
+    catch_panic((), || unsafe {
+        let ids = std::slice::from_raw_parts(sf_ids, count as usize);
+        let priorities = std::slice::from_raw_parts(priorities, count as usize);
+
+        let mut ordered: Vec<_> = sfids_to_vec(ids).into_iter().zip(priorities).collect();
+        ordered.sort_by_key(|(_, &priority)| std::cmp::Reverse(priority));
+
+        let sfvec = ordered.into_iter().map(|(sf, _)| sf).collect();
+        handle.as_mut().set_soundfonts(sfvec);
+    })
+}
+
+/// Removes all the soundfonts used in the desired channel group.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_ClearSoundfonts(handle: XSynth_ChannelGroup) {
+    catch_panic((), || {
+        handle.as_mut().set_soundfonts(Vec::new());
+    })
+}
+
+/// Saves the controller state of all channels of the desired channel group,
+/// so that it can be brought back later using XSynth_ChannelGroup_RestoreState.
+/// The state includes the program, control change values and pitch wheel
+/// position of each channel, as received through the XSynth API.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+///
+/// --Returns--
+/// This function returns the handle of the saved state. It can be used on any
+/// channel group and has to be freed using XSynth_ChannelState_Drop.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_SaveState(
+    handle: XSynth_ChannelGroup,
+) -> XSynth_ChannelState {
+    catch_panic(XSynth_ChannelState::null(), || {
+        XSynth_ChannelState::from(handle.as_ref().channel_states.clone())
+    })
+}
+
+/// Restores a controller state saved using XSynth_ChannelGroup_SaveState to
+/// the desired channel group. All control change data of each channel is
+/// reset before applying the saved values. Active notes are not affected.
+/// Disabled channels ignore the restored state, like any other event.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - state: The handle of the saved state
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_RestoreState(
+    handle: XSynth_ChannelGroup,
+    state: XSynth_ChannelState,
+) {
+    catch_panic((), || {
+        handle.as_mut().restore_channel_states(state.as_ref());
+    })
+}
+
+/// Frees a saved channel group state.
+///
+/// --Parameters--
+/// - state: The handle of the saved state
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelState_Drop(state: XSynth_ChannelState) {
+    catch_panic((), || {
+        state.drop();
+    })
+}
+
+/// Returns a JSON description of the current state of the desired channel
+/// group, including its configuration and the program, controllers and held
+/// keys of each channel. This is meant for debugging and bug reports, and the
+/// exact format may change between versions.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+///
+/// --Returns--
+/// A null terminated UTF-8 string, which has to be freed using
+/// XSynth_FreeString.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_DumpState(handle: XSynth_ChannelGroup) -> *mut c_char {
+    catch_panic(std::ptr::null_mut(), || {
+        let instance = handle.as_ref();
+        let params = instance.stream_params();
+
+        let mut muted: Vec<u32> = instance.muted_channels.iter().copied().collect();
+        muted.sort_unstable();
+
+        let channels: Vec<_> = instance
+            .channel_states
+            .iter()
+            .zip(&instance.active_keys)
+            .map(|(state, keys)| {
+                let controls: serde_json::Map<_, _> = state
+                    .controls
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(cc, value)| value.map(|v| (cc.to_string(), v.into())))
+                    .collect();
+                let held: Vec<usize> = (0..128).filter(|&key| keys[key]).collect();
+                serde_json::json!({
+                    "program": state.program,
+                    "controls": controls,
+                    "pitch_bend": state.pitch_bend,
+                    "active_keys": held,
+                })
+            })
+            .collect();
+
+        let state = serde_json::json!({
+            "sample_rate": params.sample_rate,
+            "oversampling": instance.oversampling,
+            "audio_channels": params.channels.count(),
+            "channel_count": instance.settings.channel_count,
+            "drum_channels": instance.settings.drums_channels,
+            "use_threadpool": instance.settings.use_threadpool,
+            "fade_out_killing": instance.settings.fade_out_killing,
+            "layers": instance.layers,
+            "voice_count": instance.group.voice_count(),
+            "soundfonts": instance
+                .soundfonts
+                .iter()
+                .map(|sf| sf.path.to_string_lossy())
+                .collect::<Vec<_>>(),
+            "transpose": instance.transpose,
+            "max_note_duration": instance.max_note_duration,
+            "muted_channels": muted,
+            "stereo_width": instance.effects.stereo_width,
+            "swap_channels": instance.effects.swap_channels,
+            "compressor": instance.effects.compressor.is_some(),
+            "channels": channels,
+        });
+
+        CString::new(state.to_string())
+            .unwrap_or_default()
+            .into_raw()
+    })
+}
+
+/// Frees a string returned by an XSynth function, such as
+/// XSynth_ChannelGroup_DumpState.
+///
+/// --Parameters--
+/// - string: The string to be freed
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_FreeString(string: *mut c_char) {
+    catch_panic((), || unsafe {
+        if !string.is_null() {
+            drop(CString::from_raw(string));
+        }
+    })
+}
+
+/// Options for exporting audio to a WAV file
+/// - sample_format: Format of the stored samples
+///         Supported: SAMPLE_FORMAT_F32 (32bit float), SAMPLE_FORMAT_I16 (16bit
+///         integer). SAMPLE_FORMAT_DEFAULT is the same as SAMPLE_FORMAT_F32.
+/// - big_endian: Whether integer samples are stored in big-endian byte order,
+///         for tools and hardware expecting it. The file is then written with
+///         a RIFX header instead of RIFF. Float samples are always stored in
+///         little-endian byte order, so this must be false for them.
+/// - sample_rate: Sample rate of the file, or 0 to use the sample rate of
+///         the channel group. The audio is rendered at the rate of the group
+///         and converted afterwards, so soundfonts do not have to be loaded
+///         again.
+/// - resampler: The algorithm used to convert the audio to the above sample
+///         rate. This is separate from the interpolation of the soundfont
+///         samples.
+///         Supported: RESAMPLER_LINEAR (fast), RESAMPLER_SINC (high quality)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct XSynth_ExportOptions {
+    pub sample_format: u16,
+    pub big_endian: bool,
+    pub sample_rate: u32,
+    pub resampler: u16,
+}
+
+/// Generates the default values for the XSynth_ExportOptions struct
+/// Default values are:
+/// - sample_format = SAMPLE_FORMAT_F32
+/// - big_endian = False
+/// - sample_rate = 0 (the sample rate of the channel group)
+/// - resampler = RESAMPLER_SINC
+#[no_mangle]
+pub extern "C" fn XSynth_GenDefault_ExportOptions() -> XSynth_ExportOptions {
+    XSynth_ExportOptions {
+        sample_format: SAMPLE_FORMAT_F32,
+        big_endian: false,
+        sample_rate: 0,
+        resampler: RESAMPLER_SINC,
+    }
+}
+
+fn convert_export_options(options: &XSynth_ExportOptions) -> Result<WavEncoding, String> {
+    match (options.sample_format, options.big_endian) {
+        (SAMPLE_FORMAT_DEFAULT | SAMPLE_FORMAT_F32, false) => Ok(WavEncoding::F32),
+        (SAMPLE_FORMAT_DEFAULT | SAMPLE_FORMAT_F32, true) => {
+            Err("Float samples can not be stored in big-endian byte order".to_string())
+        }
+        (SAMPLE_FORMAT_I16, big_endian) => Ok(WavEncoding::I16 { big_endian }),
+        (format, _) => Err(format!("Unsupported export sample format: {format}")),
+    }
+}
+
+/// Renders audio from the desired channel group into a complete WAV file in
+/// memory, so that it can be passed on without touching the disk. The audio
+/// goes through the same pipeline as XSynth_ChannelGroup_ReadSamples and is
+/// stored in the format given in the export options.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - total_samples: The number of audio samples to render, counting all audio
+///         channels like the length given to XSynth_ChannelGroup_ReadSamples.
+///         The resulting data must not exceed 4GB, the limit of WAV files.
+/// - options: The format of the WAV file (see XSynth_ExportOptions)
+/// - out_data: Pointer to receive the address of the WAV file data
+/// - out_len: Pointer to receive the length of the WAV file data in bytes
+///
+/// --Returns--
+/// This function returns true if the WAV file was rendered. The data then has
+/// to be freed using XSynth_FreeWav. If false is returned, nothing is written
+/// to the given pointers and the reason can be retrieved using
+/// XSynth_GetLastError.
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_ChannelGroup_ExportToMemoryWav(
+    handle: XSynth_ChannelGroup,
+    total_samples: u64,
+    options: XSynth_ExportOptions,
+    out_data: *mut *mut u8,
+    out_len: *mut u64,
+) -> bool {
+    catch_panic(false, || unsafe {
+        if out_data.is_null() || out_len.is_null() {
+            set_last_error("The output pointers must not be null");
+            return false;
+        }
+        let encoding = match convert_export_options(&options) {
+            Ok(encoding) => encoding,
+            Err(message) => {
+                set_last_error(&message);
+                return false;
+            }
+        };
+        let quality = match options.resampler {
+            RESAMPLER_LINEAR => ResamplerQuality::Linear,
+            RESAMPLER_SINC => ResamplerQuality::Sinc,
+            resampler => {
+                set_last_error(&format!("Unknown resampler: {resampler}"));
+                return false;
+            }
+        };
+
+        let instance = handle.as_mut();
+        let params = instance.stream_params();
+        let channels = params.channels.count();
+        let sample_rate = match options.sample_rate {
+            0 => params.sample_rate,
+            rate => rate,
+        };
+        let mut resampler = (sample_rate != params.sample_rate)
+            .then(|| Resampler::new(quality, params.sample_rate, sample_rate, channels as usize));
+
+        let total_samples = total_samples - total_samples % channels as u64;
+        let header = wav_header(encoding, sample_rate, channels, total_samples);
+        let data_len = encoding.data_len(total_samples);
+        let (Some(mut data), Some(data_len)) = (header, data_len) else {
+            set_last_error("The WAV file would exceed 4GB");
+            return false;
+        };
+        data.reserve(data_len);
+
+        // The group only renders the duration of the export, so that it can
+        // continue where the export ended. When resampling, the last output
+        // samples are completed with silence instead of audio past the end.
+        let total_frames = total_samples / channels as u64;
+        let mut remaining_input = (total_frames as u128 * params.sample_rate as u128
+            + sample_rate as u128 / 2)
+            / sample_rate as u128;
+        let mut buffer = vec![0.0; 4096 * channels as usize];
+        let mut resampled = Vec::new();
+        let mut remaining = total_samples as usize;
+        while remaining > 0 {
+            let frames = remaining_input.min(4096) as usize;
+            remaining_input -= frames as u128;
+            let samples = match &mut resampler {
+                Some(resampler) => {
+                    let chunk = match frames {
+                        0 => {
+                            buffer.fill(0.0);
+                            &buffer[..]
+                        }
+                        _ => {
+                            let chunk = &mut buffer[..frames * channels as usize];
+                            instance.read_samples(chunk);
+                            &chunk[..]
+                        }
+                    };
+                    resampled.clear();
+                    resampler.process(chunk, &mut resampled);
+                    &resampled[..resampled.len().min(remaining)]
+                }
+                None => {
+                    let chunk = &mut buffer[..remaining.min(4096 * channels as usize)];
+                    instance.read_samples(chunk);
+                    &chunk[..]
+                }
+            };
+            for &sample in samples.iter() {
+                encoding.write_sample(&mut data, sample);
+            }
+            remaining -= samples.len();
+        }
+
+        let data = data.into_boxed_slice();
+        *out_len = data.len() as u64;
+        *out_data = Box::into_raw(data) as *mut u8;
+        true
+    })
+}
+
+/// Frees the WAV file data returned by XSynth_ChannelGroup_ExportToMemoryWav.
+///
+/// --Parameters--
+/// - data: The address of the WAV file data
+/// - len: The length of the WAV file data in bytes, as returned along with it
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_FreeWav(data: *mut u8, len: u64) {
+    catch_panic((), || unsafe {
+        if !data.is_null() {
+            drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                data,
+                len as usize,
+            )));
+        }
+    })
+}
+
+/// Writes a human readable description of an event, such as
+/// "Ch1 NoteOn key=60 vel=100", which is useful for logging MIDI traffic. The
+/// event is decoded the same way as by XSynth_ChannelGroup_SendEvent, and the
+/// channel is shown as its MIDI channel number (channel 0 is "Ch1").
+///
+/// --Parameters--
+/// - channel: The number of the MIDI channel (MIDI channel 1 is 0)
+/// - event: The type of MIDI event (see XSynth_ChannelGroup_SendEvent)
+/// - params: Parameters for the event
+/// - out: Pointer to a mutable buffer to receive the null terminated
+///         description. Can be null to only query its length.
+/// - cap: Length of the above buffer in bytes, including the null terminator
+///
+/// --Returns--
+/// The length of the full description in bytes, excluding the null
+/// terminator. If it is not smaller than cap, the description written to the
+/// buffer is truncated.
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_DescribeEvent(
+    channel: u32,
+    event: u16,
+    params: u16,
+    out: *mut c_char,
+    cap: u64,
+) -> u64 {
+    catch_panic(0, || unsafe {
+        let description = describe_event(channel, event, params);
+        if !out.is_null() && cap > 0 {
+            let count = description.len().min(cap as usize - 1);
+            std::ptr::copy_nonoverlapping(description.as_ptr() as *const c_char, out, count);
+            *out.add(count) = 0;
+        }
+        description.len() as u64
+    })
+}
+
+/// Measures how fast the desired channel group renders audio. A copy of the
+/// group is created (see XSynth_ChannelGroup_Clone), a burst of notes is sent
+/// to it spread over its channels and keys, and the requested amount of audio
+/// is rendered through the full pipeline. The group itself is not affected.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+/// - notes: The number of note on events to send before rendering
+/// - render_samples: The number of audio samples to render
+///
+/// --Returns--
+/// The real-time factor, which is the duration of the rendered audio divided
+/// by the time it took to render it. For example, 10.0 means the group renders
+/// ten times faster than realtime.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_Benchmark(
+    handle: XSynth_ChannelGroup,
+    notes: u64,
+    render_samples: u64,
+) -> f64 {
+    catch_panic(0.0, || {
+        let mut instance = handle.as_ref().duplicate();
+        let params = instance.stream_params();
+        let channel_count = instance.settings.channel_count.max(1) as u64;
+
+        for i in 0..notes {
+            instance.send_event(SynthEvent::Channel(
+                (i % channel_count) as u32,
+                ChannelAudioEvent::NoteOn {
+                    key: (21 + i % 88) as u8,
+                    vel: 100,
+                },
+            ));
+        }
+
+        let chunk = 1024 * params.channels.count() as usize;
+        let mut buffer = vec![0.0; chunk];
+        let mut remaining = render_samples as usize;
+        let start = std::time::Instant::now();
+        while remaining > 0 {
+            let len = remaining.min(chunk);
+            instance.read_samples(&mut buffer[..len]);
+            remaining -= len;
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let frames = render_samples as f64 / params.channels.count() as f64;
+        let duration = frames / params.sample_rate as f64;
+        if elapsed > 0.0 {
+            duration / elapsed
+        } else {
+            f64::INFINITY
+        }
+    })
+}
+
+/// Checks whether the given handle refers to a channel group that has been
+/// created and not yet dropped. This allows bindings to detect the use of
+/// freed handles instead of crashing.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group
+///
+/// --Returns--
+/// This function returns true if the handle is valid, or false otherwise.
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_IsValid(handle: XSynth_ChannelGroup) -> bool {
+    catch_panic(false, || GROUPS.contains(handle.group))
+}
+
+/// Drops the desired channel group.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+#[no_mangle]
+pub extern "C" fn XSynth_ChannelGroup_Drop(handle: XSynth_ChannelGroup) {
+    catch_panic((), || {
+        handle.drop();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        realtime::*,
+        soundfont::*,
+        test_utils::{count_allocations, write_test_wav, RecordingSoundfont},
+    };
+    use xsynth_core::channel::ChannelConfigEvent;
+
+    #[test]
+    fn group_defaults_round_trip() {
+        let options = XSynth_GenDefault_GroupOptions();
+        let defaults = XSynth_GenDefault_StreamParams();
+        let handle = XSynth_ChannelGroup_Create(options);
+        assert!(XSynth_ChannelGroup_IsValid(handle));
+
+        assert_eq!(XSynth_ChannelGroup_GetStreamParams(handle), defaults);
+        assert_eq!(
+            XSynth_ChannelGroup_GetSampleRate(handle),
+            defaults.sample_rate
+        );
+        assert_eq!(
+            XSynth_ChannelGroup_GetChannelCount(handle),
+            defaults.audio_channels
+        );
+        assert_eq!(XSynth_ChannelGroup_GetLayerCount(handle), 0);
+
+        let mut drums = [0u32; 4];
+        let count = unsafe { XSynth_ChannelGroup_GetDrumChannels(handle, drums.as_mut_ptr(), 4) };
+        assert_eq!(&drums[..count as usize], &[9]);
+
+        XSynth_ChannelGroup_Drop(handle);
+        assert!(!XSynth_ChannelGroup_IsValid(handle));
+    }
+
+    #[test]
+    fn soundfont_defaults_round_trip() {
+        let path = write_test_wav("soundfont-defaults");
+        let path = CString::new(path.to_string_lossy().into_owned()).unwrap();
+        let options = XSynth_GenDefault_SoundfontOptions();
+
+        let handle = unsafe { XSynth_Soundfont_LoadWavAsInstrument(path.as_ptr(), 60, options) };
+        assert!(XSynth_Soundfont_IsValid(handle));
+        assert_eq!(
+            XSynth_Soundfont_GetInterpolator(handle),
+            options.interpolator
+        );
+        assert_eq!(
+            handle.as_ref().options.stream_params,
+            XSynth_GenDefault_StreamParams()
+        );
+
+        XSynth_Soundfont_Remove(handle);
+    }
+
+    #[test]
+    fn export_to_memory_wav_rejects_oversized_files() {
+        let handle = XSynth_ChannelGroup_Create(XSynth_GenDefault_GroupOptions());
+        let mut data = std::ptr::null_mut();
+        let mut len = 0;
+
+        for total_samples in [u64::MAX, u64::MAX / 4, 1 << 31] {
+            XSynth_ClearLastError();
+            let exported = unsafe {
+                XSynth_ChannelGroup_ExportToMemoryWav(
+                    handle,
+                    total_samples,
+                    XSynth_GenDefault_ExportOptions(),
+                    &mut data,
+                    &mut len,
+                )
+            };
+            assert!(!exported);
+            assert!(data.is_null());
+            assert!(!XSynth_GetLastError().is_null());
+        }
+
+        XSynth_ChannelGroup_Drop(handle);
+    }
+
+    #[test]
+    fn export_to_memory_wav_honors_byte_order() {
+        let handle = XSynth_ChannelGroup_Create(XSynth_GenDefault_GroupOptions());
+        let mut data = std::ptr::null_mut();
+        let mut len = 0;
+
+        let options = XSynth_ExportOptions {
+            sample_format: SAMPLE_FORMAT_I16,
+            big_endian: true,
+            ..XSynth_GenDefault_ExportOptions()
+        };
+        let exported = unsafe {
+            XSynth_ChannelGroup_ExportToMemoryWav(handle, 100, options, &mut data, &mut len)
+        };
+        assert!(exported);
+        let wav = unsafe { std::slice::from_raw_parts(data, len as usize) };
+        assert_eq!(&wav[..4], b"RIFX");
+        assert_eq!(&wav[34..36], &16u16.to_be_bytes());
+        assert_eq!(&wav[40..44], &200u32.to_be_bytes());
+        assert_eq!(len, 244);
+        unsafe { XSynth_FreeWav(data, len) };
+
+        let options = XSynth_ExportOptions {
+            sample_format: SAMPLE_FORMAT_F32,
+            big_endian: true,
+            ..XSynth_GenDefault_ExportOptions()
+        };
+        data = std::ptr::null_mut();
+        let exported = unsafe {
+            XSynth_ChannelGroup_ExportToMemoryWav(handle, 100, options, &mut data, &mut len)
+        };
+        assert!(!exported);
+        assert!(data.is_null());
+
+        XSynth_ChannelGroup_Drop(handle);
+    }
+
+    #[test]
+    fn export_to_memory_wav_converts_sample_rate() {
+        let handle = XSynth_ChannelGroup_Create(XSynth_GenDefault_GroupOptions());
+        let mut data = std::ptr::null_mut();
+        let mut len = 0;
+
+        for resampler in [RESAMPLER_LINEAR, RESAMPLER_SINC] {
+            let options = XSynth_ExportOptions {
+                sample_rate: 22050,
+                resampler,
+                ..XSynth_GenDefault_ExportOptions()
+            };
+            let exported = unsafe {
+                XSynth_ChannelGroup_ExportToMemoryWav(handle, 20000, options, &mut data, &mut len)
+            };
+            assert!(exported);
+            let wav = unsafe { std::slice::from_raw_parts(data, len as usize) };
+            assert_eq!(&wav[24..28], &22050u32.to_le_bytes());
+            assert_eq!(len, 44 + 20000 * 4);
+            unsafe { XSynth_FreeWav(data, len) };
+        }
+
+        XSynth_ChannelGroup_Drop(handle);
+    }
+
+    #[test]
+    fn export_to_memory_wav_renders_only_the_exported_length() {
+        let handle = XSynth_ChannelGroup_Create(XSynth_GenDefault_GroupOptions());
+        let rate = XSynth_ChannelGroup_GetSampleRate(handle) as u64;
+        let mut data = std::ptr::null_mut();
+        let mut len = 0;
+
+        let options = XSynth_ExportOptions {
+            sample_rate: rate as u32 / 2,
+            ..XSynth_GenDefault_ExportOptions()
+        };
+        for export in 1..=2 {
+            let exported = unsafe {
+                XSynth_ChannelGroup_ExportToMemoryWav(
+                    handle,
+                    2 * 1000,
+                    options,
+                    &mut data,
+                    &mut len,
+                )
+            };
+            assert!(exported);
+            assert_eq!(len, 44 + 2 * 1000 * 4);
+            unsafe { XSynth_FreeWav(data, len) };
+            // 1000 frames at half the rate of the group are 2000 of its frames.
+            assert_eq!(handle.as_ref().clock, export * 2000);
+        }
+
+        let mut buffer = vec![0.0f32; 2 * 100];
+        unsafe { XSynth_ChannelGroup_ReadSamples(handle, buffer.as_mut_ptr(), 2 * 100) };
+        assert_eq!(handle.as_ref().clock, 4100);
+
+        XSynth_ChannelGroup_Drop(handle);
+    }
+
+    #[test]
+    fn soundfont_load_failure_sets_error() {
+        let options = XSynth_GenDefault_SoundfontOptions();
+        let missing = std::env::temp_dir().join("xsynth-test-missing.sf2");
+        let missing = CString::new(missing.to_string_lossy().into_owned()).unwrap();
+
+        for path in [missing.as_ptr(), std::ptr::null()] {
+            XSynth_ClearLastError();
+            let handle = XSynth_Soundfont_LoadNew(path, options);
+            assert!(handle.soundfont.is_null());
+            assert!(!XSynth_Soundfont_IsValid(handle));
+            assert!(!XSynth_GetLastError().is_null());
+        }
+    }
+
+    #[test]
+    fn synth_handle_unknown_kind_sets_error() {
+        let group = XSynth_ChannelGroup_Create(XSynth_GenDefault_GroupOptions());
+        let handle = XSynth_SynthHandle_FromChannelGroup(group);
+        assert_eq!(handle.kind, SYNTH_KIND_CHANNEL_GROUP);
+        assert_eq!(handle.synth, group.group);
+
+        XSynth_ClearLastError();
+        let unknown = XSynth_SynthHandle {
+            kind: 0xFFFF,
+            ..handle
+        };
+        assert_eq!(XSynth_Synth_VoiceCount(unknown), 0);
+        let error = unsafe { std::ffi::CStr::from_ptr(XSynth_GetLastError()) };
+        assert_eq!(error.to_str().unwrap(), "Unknown synth handle kind");
+
+        XSynth_ChannelGroup_Drop(group);
+    }
+
+    #[test]
+    fn version_strings_are_valid() {
+        for version in [
+            XSynth_GetCoreVersionString(),
+            XSynth_GetRealtimeVersionString(),
+        ] {
+            assert!(!version.is_null());
+            let version = unsafe { std::ffi::CStr::from_ptr(version) };
+            assert!(!version.to_str().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn read_samples_does_not_allocate_after_prepare() {
+        let options = XSynth_GroupOptions {
+            use_threadpool: false,
+            ..XSynth_GenDefault_GroupOptions()
+        };
+        let handle = XSynth_ChannelGroup_Create(options);
+        let mut f64_buffer = vec![0.0f64; 1024];
+        let mut mono_buffer = vec![0.0f32; 512];
+        XSynth_ChannelGroup_PrepareForLength(handle, 1024);
+
+        let mut read = || unsafe {
+            XSynth_ChannelGroup_ReadSamplesF64(handle, f64_buffer.as_mut_ptr(), 1024);
+            XSynth_ChannelGroup_ReadSamplesAs(
+                handle,
+                mono_buffer.as_mut_ptr(),
+                512,
+                AUDIO_CHANNELS_MONO,
+            );
+        };
+        // The renderer itself allocates its buffers during the first read.
+        read();
+        assert_eq!(count_allocations(read), 0);
+
+        XSynth_ChannelGroup_Drop(handle);
+    }
+
+    #[test]
+    fn max_voices_kills_the_oldest_voices() {
+        let path = write_test_wav("max-voices");
+        let path = CString::new(path.to_string_lossy().into_owned()).unwrap();
+        let soundfont = unsafe {
+            XSynth_Soundfont_LoadWavAsInstrument(
+                path.as_ptr(),
+                60,
+                XSynth_GenDefault_SoundfontOptions(),
+            )
+        };
+        assert!(XSynth_Soundfont_IsValid(soundfont));
+
+        let options = XSynth_GroupOptions {
+            fade_out_killing: false,
+            ..XSynth_GenDefault_GroupOptions()
+        };
+        let group = XSynth_ChannelGroup_Create(options);
+        let ids = [soundfont];
+        unsafe { XSynth_ChannelGroup_SetSoundfonts(group, ids.as_ptr(), 1) };
+        XSynth_ChannelGroup_SetMaxVoices(group, 4);
+
+        let mut buffer = vec![0.0f32; 512];
+        for key in 40..52u16 {
+            XSynth_ChannelGroup_SendEvent(group, 0, MIDI_EVENT_NOTEON, key | (100 << 8));
+            unsafe { XSynth_ChannelGroup_ReadSamples(group, buffer.as_mut_ptr(), 512) };
+            assert!(XSynth_ChannelGroup_VoiceCount(group) <= 4);
+        }
+        // The held notes are cut rather than released, so only the newest
+        // ones keep playing.
+        assert_eq!(XSynth_ChannelGroup_VoiceCount(group), 4);
+
+        XSynth_ChannelGroup_Drop(group);
+        XSynth_Soundfont_Remove(soundfont);
+    }
+
+    #[test]
+    fn realtime_max_voices_kills_the_oldest_voices() {
+        let path = write_test_wav("realtime-max-voices");
+        let path = CString::new(path.to_string_lossy().into_owned()).unwrap();
+        let soundfont = unsafe {
+            XSynth_Soundfont_LoadWavAsInstrument(
+                path.as_ptr(),
+                60,
+                XSynth_GenDefault_SoundfontOptions(),
+            )
+        };
+        assert!(XSynth_Soundfont_IsValid(soundfont));
+
+        let synth = XSynth_Realtime_CreateHeadless(
+            XSynth_GenDefault_RealtimeConfig(),
+            XSynth_GenDefault_StreamParams(),
+        );
+        let ids = [soundfont];
+        XSynth_Realtime_SetSoundfonts(synth, ids.as_ptr(), 1);
+        XSynth_Realtime_SetMaxVoices(synth, 3);
+        assert_eq!(XSynth_Realtime_GetMaxVoices(synth), 3);
+
+        let mut buffer = vec![0.0f32; 512];
+        for key in 60..70u16 {
+            XSynth_Realtime_SendEvent(synth, 0, MIDI_EVENT_NOTEON, key | (100 << 8));
+            unsafe { XSynth_Realtime_ReadSamples(synth, buffer.as_mut_ptr(), 512) };
+            assert!(XSynth_Realtime_GetStats(synth).voice_count <= 3);
+        }
+        assert_eq!(XSynth_Realtime_GetStats(synth).voice_count, 3);
+
+        // Lifting the limit lets new voices play alongside the old ones.
+        XSynth_Realtime_SetMaxVoices(synth, 0);
+        XSynth_Realtime_SendEvent(synth, 0, MIDI_EVENT_NOTEON, 70 | (100 << 8));
+        unsafe { XSynth_Realtime_ReadSamples(synth, buffer.as_mut_ptr(), 512) };
+        assert_eq!(XSynth_Realtime_GetStats(synth).voice_count, 4);
+
+        XSynth_Realtime_Drop(synth);
+        XSynth_Soundfont_Remove(soundfont);
+    }
+
+    #[test]
+    fn max_voices_round_trip() {
+        let group = XSynth_ChannelGroup_Create(XSynth_GenDefault_GroupOptions());
+        assert_eq!(XSynth_ChannelGroup_GetMaxVoices(group), 0);
+        XSynth_ChannelGroup_SetMaxVoices(group, 500);
+        assert_eq!(XSynth_ChannelGroup_GetMaxVoices(group), 500);
+        let copy = XSynth_ChannelGroup_Clone(group);
+        assert_eq!(XSynth_ChannelGroup_GetMaxVoices(copy), 500);
+        XSynth_ChannelGroup_Drop(copy);
+        XSynth_ChannelGroup_Drop(group);
+
+        let synth = XSynth_Realtime_CreateHeadless(
+            XSynth_GenDefault_RealtimeConfig(),
+            XSynth_GenDefault_StreamParams(),
+        );
+        assert_eq!(XSynth_Realtime_GetMaxVoices(synth), 0);
+        XSynth_Realtime_SetMaxVoices(synth, 250);
+        assert_eq!(XSynth_Realtime_GetMaxVoices(synth), 250);
+        XSynth_Realtime_Drop(synth);
+    }
+
+    #[test]
+    fn drum_program_change_selects_percussion_bank() {
+        let handle = XSynth_ChannelGroup_Create(XSynth_GenDefault_GroupOptions());
+        let instance = handle.as_mut();
+        let soundfont = Arc::new(RecordingSoundfont::new(*instance.group.stream_params()));
+        instance.group.send_event(SynthEvent::ChannelConfig(
+            ChannelConfigEvent::SetSoundfonts(vec![soundfont.clone()]),
+        ));
+
+        for channel in [0, 9] {
+            XSynth_ChannelGroup_SendEvent(handle, channel, MIDI_EVENT_PROGRAMCHANGE, 25);
+            XSynth_ChannelGroup_SendEvent(handle, channel, MIDI_EVENT_NOTEON, (100 << 8) | 60);
+        }
+        let mut buffer = vec![0.0; 256];
+        unsafe { XSynth_ChannelGroup_ReadSamples(handle, buffer.as_mut_ptr(), 256) };
+
+        let requested = soundfont.requested.lock().unwrap();
+        assert!(requested.contains(&(0, 25)));
+        assert!(requested.contains(&(128, 25)));
+        assert!(!requested.contains(&(0, 0)));
+
+        XSynth_ChannelGroup_Drop(handle);
+    }
+
+    #[test]
+    fn pitch_bend_range_survives_state_restore() {
+        let handle = XSynth_ChannelGroup_Create(XSynth_GenDefault_GroupOptions());
+        XSynth_ChannelGroup_SetPitchBendRange(handle, 0, 12.5);
+        let state = &handle.as_ref().channel_states[0];
+        assert_eq!(state.controls[101], Some(127));
+        assert_eq!(state.controls[100], Some(127));
+        assert_eq!(state.pitch_bend_range, Some((12, Some(50))));
+
+        let saved = XSynth_ChannelGroup_SaveState(handle);
+        let copy = XSynth_ChannelGroup_Create(XSynth_GenDefault_GroupOptions());
+        XSynth_ChannelGroup_RestoreState(copy, saved);
+        let state = &copy.as_ref().channel_states[0];
+        assert_eq!(state.controls[101], Some(127));
+        assert_eq!(state.pitch_bend_range, Some((12, Some(50))));
+
+        XSynth_ChannelState_Drop(saved);
+        XSynth_ChannelGroup_Drop(copy);
+        XSynth_ChannelGroup_Drop(handle);
+    }
+
+    #[test]
+    fn wav_instrument_keeps_the_wav_path() {
+        let wav_path = write_test_wav("wav-instrument-path")
+            .canonicalize()
+            .unwrap();
+        let path = CString::new(wav_path.to_string_lossy().into_owned()).unwrap();
+        let options = XSynth_GenDefault_SoundfontOptions();
+
+        let handle = unsafe { XSynth_Soundfont_LoadWavAsInstrument(path.as_ptr(), 60, options) };
+        assert!(XSynth_Soundfont_IsValid(handle));
+        let loaded_path = unsafe { std::ffi::CStr::from_ptr(XSynth_Soundfont_GetPath(handle)) };
+        assert_eq!(loaded_path, path.as_c_str());
+        assert_eq!(XSynth_Soundfont_ReloadFromDisk(handle), RESULT_OK);
+
+        XSynth_Soundfont_Remove(handle);
+    }
+
+    #[test]
+    fn effects_state_reports_every_effect() {
+        let group = XSynth_ChannelGroup_Create(XSynth_GenDefault_GroupOptions());
+        XSynth_ChannelGroup_SetCompressor(group, true, -12.0, 4.0, 5.0, 100.0);
+        XSynth_ChannelGroup_SetOutputClamp(group, OUTPUT_CLAMP_TANH);
+
+        let mut state = XSynth_EffectsState {
+            struct_size: std::mem::size_of::<XSynth_EffectsState>() as u32,
+            ..Default::default()
+        };
+        assert!(unsafe { XSynth_ChannelGroup_GetEffectsState(group, &mut state) });
+        assert_eq!(
+            state.struct_size as usize,
+            std::mem::size_of::<XSynth_EffectsState>()
+        );
+        assert!(state.compressor_enabled);
+        assert_eq!(state.compressor_threshold, -12.0);
+        assert_eq!(state.compressor_ratio, 4.0);
+        assert_eq!(state.master_gain, 1.0);
+        assert!(!state.limiter_enabled);
+        assert_eq!(state.output_clamp, OUTPUT_CLAMP_TANH);
+        assert!(!state.filter_enabled);
+
+        // An older caller only gets the fields that fit in its struct.
+        let mut old = XSynth_EffectsState {
+            struct_size: 8,
+            stereo_width: -1.0,
+            ..Default::default()
+        };
+        assert!(unsafe { XSynth_ChannelGroup_GetEffectsState(group, &mut old) });
+        assert_eq!(old.struct_size, 8);
+        assert!(!old.dc_filter_enabled);
+        assert_eq!(old.stereo_width, -1.0);
+
+        XSynth_ChannelGroup_Drop(group);
+    }
+
+    #[test]
+    fn realtime_volume_is_smoothed() {
+        let params = XSynth_GenDefault_StreamParams();
+        let handle = XSynth_Realtime_CreateHeadless(XSynth_GenDefault_RealtimeConfig(), params);
+        XSynth_Realtime_SetVolume(handle, -1.0);
+        assert_eq!(handle.as_ref().volume.target, 0.0);
+        XSynth_Realtime_Drop(handle);
+
+        let mut volume = effects::SmoothedGain::new(1.0);
+        volume.target = 0.0;
+        let mut buffer = vec![1.0f32; 48000 / 10 * 2];
+        volume.process(&mut buffer, 2, 48000);
+        // The volume ramps down instead of jumping, and settles within 100ms.
+        assert!(buffer[0] > 0.99);
+        assert!(buffer.windows(2).all(|w| w[1] <= w[0]));
+        assert!(buffer[buffer.len() - 1] < 0.05);
+    }
+
+    #[test]
+    fn oversampling_keeps_output_params() {
+        let group = XSynth_ChannelGroup_Create(XSynth_GenDefault_GroupOptions());
+        let params = XSynth_ChannelGroup_GetStreamParams(group);
+        assert!(!XSynth_ChannelGroup_SetOversampling(group, 3));
+        assert!(XSynth_ChannelGroup_SetOversampling(group, 4));
+        assert_eq!(XSynth_ChannelGroup_GetOversampling(group), 4);
+        assert_eq!(XSynth_ChannelGroup_GetStreamParams(group), params);
+        assert_eq!(
+            group.as_ref().group.stream_params().sample_rate,
+            params.sample_rate * 4
+        );
+
+        // Reads of any length return exactly the requested audio.
+        for length in [1000, 6] {
+            let mut buffer = vec![1.0f32; length];
+            unsafe {
+                XSynth_ChannelGroup_ReadSamples(group, buffer.as_mut_ptr(), length as u64);
+            }
+            assert!(buffer.iter().all(|s| *s == 0.0));
+        }
+
+        let copy = XSynth_ChannelGroup_Clone(group);
+        assert_eq!(XSynth_ChannelGroup_GetOversampling(copy), 4);
+        XSynth_ChannelGroup_Drop(copy);
+        XSynth_ChannelGroup_Drop(group);
+    }
+
+    #[test]
+    fn handle_mode_switch_waits_for_untracked_handles() {
+        let registry = crate::registry::HandleRegistry::new();
+        let handle = Box::into_raw(Box::new(0u8)) as *mut std::ffi::c_void;
+
+        assert!(registry.set_enabled(false));
+        registry.insert(handle);
+        // The handle is not tracked, but still alive.
+        assert!(!registry.contains(handle));
+        assert!(!registry.set_enabled(true));
+
+        registry.remove(handle);
+        assert!(registry.set_enabled(true));
+        unsafe { drop(Box::from_raw(handle as *mut u8)) };
+    }
+
+    #[test]
+    fn load_wav_as_instrument_rejects_null_path() {
+        XSynth_ClearLastError();
+        let handle = unsafe {
+            XSynth_Soundfont_LoadWavAsInstrument(
+                std::ptr::null(),
+                60,
+                XSynth_GenDefault_SoundfontOptions(),
+            )
+        };
+        assert!(handle.soundfont.is_null());
+        assert!(!XSynth_GetLastError().is_null());
+    }
+
+    #[test]
+    fn load_wav_as_instrument_accepts_sfz_syntax_in_path() {
+        let wav_path = write_test_wav("sfz <region> key=60 sample=x");
+        let path = CString::new(wav_path.to_string_lossy().into_owned()).unwrap();
+        let options = XSynth_GenDefault_SoundfontOptions();
+
+        let handle = unsafe { XSynth_Soundfont_LoadWavAsInstrument(path.as_ptr(), 60, options) };
+        assert!(XSynth_Soundfont_IsValid(handle));
+        assert_eq!(XSynth_Soundfont_ReloadFromDisk(handle), RESULT_OK);
+        // Deleting the linked copy leaves the original file in place.
+        assert!(wav_path.exists());
+
+        XSynth_Soundfont_Remove(handle);
+    }
+
+    #[test]
+    fn soundfont_gain_scales_its_voices() {
+        let path = write_test_wav("soundfont-gain");
+        let path = CString::new(path.to_string_lossy().into_owned()).unwrap();
+        let soundfont = unsafe {
+            XSynth_Soundfont_LoadWavAsInstrument(
+                path.as_ptr(),
+                60,
+                XSynth_GenDefault_SoundfontOptions(),
+            )
+        };
+        assert!(XSynth_Soundfont_IsValid(soundfont));
+
+        let render_peak = || {
+            let group = XSynth_ChannelGroup_Create(XSynth_GenDefault_GroupOptions());
+            let ids = [soundfont];
+            unsafe { XSynth_ChannelGroup_SetSoundfonts(group, ids.as_ptr(), 1) };
+            XSynth_ChannelGroup_SendEvent(group, 0, MIDI_EVENT_NOTEON, 60 | (127 << 8));
+            let mut buffer = vec![0.0f32; 4096];
+            unsafe { XSynth_ChannelGroup_ReadSamples(group, buffer.as_mut_ptr(), 4096) };
+            XSynth_ChannelGroup_Drop(group);
+            buffer.iter().fold(0.0f32, |peak, s| peak.max(s.abs()))
+        };
+
+        let unity = render_peak();
+        assert!(unity > 0.0);
+        XSynth_Soundfont_SetGain(soundfont, -20.0);
+        let attenuated = render_peak();
+        assert!((attenuated / unity - 0.1).abs() < 1e-3);
+
+        XSynth_Soundfont_Remove(soundfont);
+    }
+
+    #[test]
+    fn device_volume_fades_playing_voices() {
+        let path = write_test_wav("device-volume");
+        let path = CString::new(path.to_string_lossy().into_owned()).unwrap();
+        let soundfont = unsafe {
+            XSynth_Soundfont_LoadWavAsInstrument(
+                path.as_ptr(),
+                60,
+                XSynth_GenDefault_SoundfontOptions(),
+            )
+        };
+        assert!(XSynth_Soundfont_IsValid(soundfont));
+
+        // Realtime synths playing to a device wrap their soundfonts the same
+        // way, which can't be tested without an audio device.
+        let volume = Arc::new(std::sync::atomic::AtomicU32::new(1.0f32.to_bits()));
+        let soundfonts = crate::voices::GainSoundfont::wrap(
+            &[soundfont.clone() as Arc<dyn xsynth_core::soundfont::SoundfontBase>],
+            &volume,
+        );
+        let group = XSynth_ChannelGroup_Create(XSynth_GenDefault_GroupOptions());
+        let instance = group.as_mut();
+        instance.group.send_event(SynthEvent::ChannelConfig(
+            ChannelConfigEvent::SetSoundfonts(soundfonts),
+        ));
+        instance.send_event(SynthEvent::Channel(
+            0,
+            ChannelAudioEvent::NoteOn { key: 60, vel: 127 },
+        ));
+        let mut buffer = vec![0.0f32; 512];
+        instance.read_samples(&mut buffer);
+        let peak = buffer.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak > 0.0);
+
+        volume.store(0.0f32.to_bits(), std::sync::atomic::Ordering::Relaxed);
+        // 60ms, which is still within the sample.
+        let mut buffer = vec![0.0f32; 2 * 2880];
+        instance.read_samples(&mut buffer);
+        // The volume falls over about 30ms instead of cutting off.
+        assert!(buffer[..64].iter().any(|s| s.abs() > peak * 0.5));
+        assert!(buffer[buffer.len() - 200..]
+            .iter()
+            .all(|s| s.abs() < peak * 0.2));
+
+        XSynth_ChannelGroup_Drop(group);
+        XSynth_Soundfont_Remove(soundfont);
+    }
+
+    #[test]
+    fn supported_interpolators_follow_features() {
+        let count = unsafe { XSynth_GetSupportedInterpolators(std::ptr::null_mut(), 0) };
+        let mut supported = vec![0u16; count as usize];
+        let written = unsafe { XSynth_GetSupportedInterpolators(supported.as_mut_ptr(), count) };
+        assert_eq!(written, count);
+        assert!(supported.contains(&INTERPOLATION_NEAREST));
+        assert_eq!(
+            supported.contains(&INTERPOLATION_LINEAR),
+            cfg!(feature = "interpolation-linear")
+        );
+    }
+
+    #[test]
+    fn headless_realtime_defaults_round_trip() {
+        let defaults = XSynth_GenDefault_StreamParams();
+        let handle = XSynth_Realtime_CreateHeadless(XSynth_GenDefault_RealtimeConfig(), defaults);
+        assert!(XSynth_Realtime_IsValid(handle));
+
+        assert_eq!(XSynth_Realtime_GetStreamParams(handle), defaults);
+        assert_eq!(XSynth_Realtime_GetLayerCount(handle), 0);
+        assert_eq!(handle.as_ref().settings.group.drums_channels, vec![9]);
+
+        XSynth_Realtime_Drop(handle);
+    }
 }
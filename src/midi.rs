@@ -0,0 +1,113 @@
+use xsynth_core::{
+    channel::{ChannelAudioEvent, ControlEvent},
+    channel_group::SynthEvent,
+};
+
+/// A complete message read from a raw MIDI byte stream.
+pub(crate) enum MidiMessage {
+    Event(SynthEvent),
+    /// A SysEx message, including the leading 0xF0 and trailing 0xF7 bytes.
+    SysEx(Vec<u8>),
+}
+
+/// Parses raw MIDI bytes into messages. The state is kept between calls, so
+/// running status and SysEx messages split across multiple buffers are
+/// handled correctly.
+#[derive(Default)]
+pub(crate) struct MidiParser {
+    status: Option<u8>,
+    data: Vec<u8>,
+    sysex: Option<Vec<u8>>,
+}
+
+/// Returns the number of data bytes following the given status byte.
+fn data_length(status: u8) -> usize {
+    match status & 0xF0 {
+        0xC0 | 0xD0 => 1,
+        0x80..=0xE0 => 2,
+        _ => match status {
+            0xF1 | 0xF3 => 1,
+            0xF2 => 2,
+            _ => 0,
+        },
+    }
+}
+
+impl MidiParser {
+    pub(crate) fn parse(&mut self, bytes: &[u8], mut emit: impl FnMut(MidiMessage)) {
+        for &byte in bytes {
+            // System realtime messages can appear anywhere and do not
+            // interrupt other messages.
+            if byte >= 0xF8 {
+                continue;
+            }
+
+            if let Some(sysex) = &mut self.sysex {
+                if byte & 0x80 == 0 {
+                    sysex.push(byte);
+                    continue;
+                }
+                // Any status byte ends a SysEx message.
+                let mut sysex = self.sysex.take().unwrap_or_default();
+                sysex.push(0xF7);
+                emit(MidiMessage::SysEx(sysex));
+                if byte == 0xF7 {
+                    continue;
+                }
+            }
+
+            if byte & 0x80 != 0 {
+                self.data.clear();
+                match byte {
+                    0xF0 => {
+                        self.status = None;
+                        self.sysex = Some(vec![0xF0]);
+                    }
+                    // Stray end of SysEx
+                    0xF7 => self.status = None,
+                    _ => self.status = Some(byte),
+                }
+                continue;
+            }
+
+            let Some(status) = self.status else {
+                continue;
+            };
+            self.data.push(byte);
+            if self.data.len() < data_length(status) {
+                continue;
+            }
+
+            if status < 0xF0 {
+                if let Some(event) = convert_message(status, &self.data) {
+                    emit(MidiMessage::Event(event));
+                }
+            } else {
+                // System common messages are ignored, and cancel running
+                // status.
+                self.status = None;
+            }
+            self.data.clear();
+        }
+    }
+}
+
+fn convert_message(status: u8, data: &[u8]) -> Option<SynthEvent> {
+    let channel = (status & 0x0F) as u32;
+    let event = match status & 0xF0 {
+        0x80 => ChannelAudioEvent::NoteOff { key: data[0] },
+        0x90 if data[1] == 0 => ChannelAudioEvent::NoteOff { key: data[0] },
+        0x90 => ChannelAudioEvent::NoteOn {
+            key: data[0],
+            vel: data[1],
+        },
+        0xB0 => ChannelAudioEvent::Control(ControlEvent::Raw(data[0], data[1])),
+        0xC0 => ChannelAudioEvent::ProgramChange(data[0]),
+        0xE0 => {
+            let value = (data[0] as u16 | (data[1] as u16) << 7) as f32;
+            ChannelAudioEvent::Control(ControlEvent::PitchBendValue((value - 8192.0) / 8192.0))
+        }
+        _ => return None,
+    };
+    Some(SynthEvent::Channel(channel, event))
+}
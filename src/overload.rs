@@ -0,0 +1,91 @@
+use std::{
+    ffi::c_void,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+use xsynth_realtime::RealtimeSynthStatsReader;
+
+/// How often the render load of a realtime synth is checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Default render load above which the overload callback is called.
+pub(crate) const DEFAULT_OVERLOAD_THRESHOLD: f64 = 0.8;
+
+/// A callback registered using XSynth_Realtime_SetOverloadCallback.
+#[derive(Clone, Copy)]
+struct OverloadCallback {
+    func: unsafe extern "C" fn(f64, u64, *mut c_void),
+    user: *mut c_void,
+}
+
+// The callback is provided by the caller, who is responsible for making it
+// safe to call from the monitoring thread.
+unsafe impl Send for OverloadCallback {}
+
+/// Watches the render load of a realtime synth on a separate thread, and
+/// calls the registered callback when it crosses the threshold.
+pub(crate) struct OverloadMonitor {
+    callback: OverloadCallback,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl OverloadMonitor {
+    pub(crate) fn start(
+        func: unsafe extern "C" fn(f64, u64, *mut c_void),
+        user: *mut c_void,
+        threshold: Arc<AtomicU64>,
+        stats: RealtimeSynthStatsReader,
+    ) -> Self {
+        let mut monitor = Self {
+            callback: OverloadCallback { func, user },
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        };
+        monitor.restart(threshold, stats);
+        monitor
+    }
+
+    /// Starts watching a new synth, for example after it has been rebuilt.
+    pub(crate) fn restart(&mut self, threshold: Arc<AtomicU64>, stats: RealtimeSynthStatsReader) {
+        self.stop_thread();
+        self.stop = Arc::new(AtomicBool::new(false));
+
+        let stop = self.stop.clone();
+        let callback = self.callback;
+        self.thread = Some(std::thread::spawn(move || {
+            // Moves the whole struct into the closure, which is Send, rather
+            // than its raw pointer field on its own.
+            let callback = callback;
+            let mut overloaded = false;
+            while !stop.load(Ordering::Relaxed) {
+                let load = stats.buffer().average_renderer_load();
+                let threshold = f64::from_bits(threshold.load(Ordering::Relaxed));
+                // Only called when the load rises above the threshold, not
+                // on every check while it stays there.
+                if load >= threshold && !overloaded {
+                    unsafe { (callback.func)(load, stats.voice_count(), callback.user) };
+                }
+                overloaded = load >= threshold;
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }));
+    }
+
+    fn stop_thread(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for OverloadMonitor {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
@@ -1,12 +1,81 @@
 use crate::{
-    convert_event, convert_layer_count, convert_streamparams_to_c, sfids_to_vec,
-    XSynth_RealtimeSynth, XSynth_Soundfont, XSynth_StreamParams,
+    consts::*,
+    convert_event, convert_layer_count, convert_streamparams_to_c, convert_streamparams_to_rust,
+    error::{catch_panic, set_last_error},
+    handles::{GroupSettings, RealtimeBackend, RealtimeSettings},
+    is_reset_sysex,
+    midi::MidiMessage,
+    overload::OverloadMonitor,
+    registry::REALTIME_SYNTHS,
+    sfids_to_vec, XSynth_RealtimeSynth, XSynth_Soundfont, XSynth_StreamParams,
 };
-use xsynth_core::{
-    channel::{ChannelConfigEvent, ChannelInitOptions},
-    channel_group::SynthEvent,
+use cpal::{
+    traits::{DeviceTrait, HostTrait},
+    Device, SampleFormat, SupportedBufferSize, SupportedStreamConfig,
 };
-use xsynth_realtime::{RealtimeSynth, XSynthRealtimeConfig};
+use std::{
+    ffi::{c_char, c_void, CStr, CString},
+    sync::atomic::Ordering,
+};
+use xsynth_core::AudioPipe;
+use xsynth_realtime::RealtimeSynth;
+
+/// Sample rates reported by XSynth_Realtime_GetDeviceSampleRates when a
+/// device supports a range of rates.
+const COMMON_SAMPLE_RATES: [u32; 11] = [
+    8000, 11025, 16000, 22050, 32000, 44100, 48000, 88200, 96000, 176400, 192000,
+];
+
+/// Finds an audio output device by name, or the default output device if no
+/// name is given.
+unsafe fn find_output_device(name: *const c_char) -> Option<Device> {
+    let host = cpal::default_host();
+    if name.is_null() {
+        return host.default_output_device();
+    }
+
+    let name = CStr::from_ptr(name).to_str().ok()?;
+    host.output_devices()
+        .ok()?
+        .find(|device| device.name().is_ok_and(|n| n == name))
+}
+
+/// Finds an output configuration of the given device using the requested
+/// sample format. The default sample rate of the device is kept if the format
+/// supports it.
+fn find_output_config(device: &Device, sample_format: u16) -> SupportedStreamConfig {
+    let default = device
+        .default_output_config()
+        .unwrap_or_else(|_| panic!("Failed to get the audio output configuration"));
+
+    let format = match sample_format {
+        SAMPLE_FORMAT_DEFAULT => return default,
+        SAMPLE_FORMAT_F32 => SampleFormat::F32,
+        SAMPLE_FORMAT_I16 => SampleFormat::I16,
+        SAMPLE_FORMAT_U16 => SampleFormat::U16,
+        _ => panic!("Unknown sample format {sample_format}"),
+    };
+    if default.sample_format() == format {
+        return default;
+    }
+
+    let sample_rate = default.sample_rate();
+    device
+        .supported_output_configs()
+        .unwrap_or_else(|_| panic!("Failed to get the audio output configuration"))
+        .filter(|range| range.sample_format() == format)
+        .map(|range| {
+            if range.min_sample_rate() <= sample_rate && sample_rate <= range.max_sample_rate() {
+                range.with_sample_rate(sample_rate)
+            } else {
+                range.with_max_sample_rate()
+            }
+        })
+        .max_by_key(|config| config.sample_rate() == sample_rate)
+        .unwrap_or_else(|| {
+            panic!("The audio output device does not support the {format} sample format")
+        })
+}
 
 /// Options for initializing the XSynth Realtime module
 /// - channels: Number of MIDI channels
@@ -17,6 +86,9 @@ use xsynth_realtime::{RealtimeSynth, XSynthRealtimeConfig};
 /// - render_window_ms: The length of the buffer reader in ms
 /// - ignore_range: A range of velocities that will not be played
 ///         LOBYTE = start (0-127), HIBYTE = end (start-127)
+/// - sample_format: The sample format of the audio output device
+///         Supported: SAMPLE_FORMAT_DEFAULT (device default), SAMPLE_FORMAT_F32,
+///         SAMPLE_FORMAT_I16, SAMPLE_FORMAT_U16
 #[repr(C)]
 pub struct XSynth_RealtimeConfig {
     pub channels: u32,
@@ -26,6 +98,7 @@ pub struct XSynth_RealtimeConfig {
     pub fade_out_killing: bool,
     pub render_window_ms: f64,
     pub ignore_range: u16,
+    pub sample_format: u16,
 }
 
 /// Generates the default values for the XSynth_RealtimeConfig struct
@@ -37,6 +110,7 @@ pub struct XSynth_RealtimeConfig {
 /// - fade_out_killing: False
 /// - render_window_ms: 10.0ms
 /// - ignore_range: 0->0 (Nothing ignored)
+/// - sample_format: SAMPLE_FORMAT_DEFAULT
 #[no_mangle]
 pub extern "C" fn XSynth_GenDefault_RealtimeConfig() -> XSynth_RealtimeConfig {
     XSynth_RealtimeConfig {
@@ -47,6 +121,7 @@ pub extern "C" fn XSynth_GenDefault_RealtimeConfig() -> XSynth_RealtimeConfig {
         fade_out_killing: false,
         render_window_ms: 10.0,
         ignore_range: 0,
+        sample_format: SAMPLE_FORMAT_DEFAULT,
     }
 }
 
@@ -56,12 +131,71 @@ pub extern "C" fn XSynth_GenDefault_RealtimeConfig() -> XSynth_RealtimeConfig {
 /// - buffer: Number of samples requested in the last read
 /// - render_time: Percentage of the renderer load
 #[repr(C)]
+#[derive(Default)]
 pub struct XSynth_RealtimeStats {
     pub voice_count: u64,
     pub buffer: i64,
     pub render_time: f64,
 }
 
+/// Extended statistics of a realtime synth instance, read using
+/// XSynth_Realtime_GetStatsV2. New fields are only ever added at the end, so
+/// applications built against an older version of this struct keep working.
+/// - voice_count: The amount of active voices
+/// - buffer: Number of samples requested in the last read
+/// - render_time: Percentage of the renderer load
+/// - scheduled_events: Number of events sent using
+///         XSynth_Realtime_SendEventTimestamped that were not applied yet
+/// - rendered_frames: Number of audio frames rendered so far by a headless
+///         synth, or 0 for a synth playing to an audio device
+#[repr(C)]
+#[derive(Default)]
+pub struct XSynth_RealtimeStatsV2 {
+    pub voice_count: u64,
+    pub buffer: i64,
+    pub render_time: f64,
+    pub scheduled_events: u64,
+    pub rendered_frames: u64,
+}
+
+unsafe fn convert_realtime_config(config: &XSynth_RealtimeConfig) -> RealtimeSettings {
+    let drumvec =
+        std::slice::from_raw_parts(config.drum_channels, config.drum_channels_count as usize);
+
+    let ignore_range = {
+        let low = (config.ignore_range & 255) as u8;
+        let high = (config.ignore_range >> 8) as u8;
+        low..=high
+    };
+
+    RealtimeSettings {
+        group: GroupSettings {
+            channel_count: config.channels,
+            drums_channels: Vec::from(drumvec),
+            use_threadpool: config.use_threadpool,
+            fade_out_killing: config.fade_out_killing,
+        },
+        render_window_ms: config.render_window_ms,
+        ignore_range,
+    }
+}
+
+/// The audio output configuration a realtime synth instance is actually
+/// using, which may differ from the requested one.
+/// - sample_rate: Audio sample rate
+/// - audio_channels: Number of audio channels
+/// - buffer_size_min: Minimum buffer size supported by the device, in samples
+/// - buffer_size_max: Maximum buffer size supported by the device, in samples
+///         Both buffer sizes are 0 if they are not known.
+#[repr(C)]
+#[derive(Default)]
+pub struct XSynth_ActualStreamConfig {
+    pub sample_rate: u32,
+    pub audio_channels: u16,
+    pub buffer_size_min: u32,
+    pub buffer_size_max: u32,
+}
+
 /// Initializes the XSynth Realtime module with the given configuration.
 ///
 /// --Parameters--
@@ -70,36 +204,59 @@ pub struct XSynth_RealtimeStats {
 /// --Returns--
 /// This function will return the handle of the created realtime synthesizer.
 /// This will be necessary to use other XSynth_Realtime_* functions, for the
-/// specific synthesizer instance.
+/// specific synthesizer instance. If no audio output device is available, or
+/// it does not support the requested sample format, a null handle is returned
+/// and the reason can be retrieved using XSynth_GetLastError.
 #[no_mangle]
 pub extern "C" fn XSynth_Realtime_Create(config: XSynth_RealtimeConfig) -> XSynth_RealtimeSynth {
-    unsafe {
-        let channel_init_options = ChannelInitOptions {
-            fade_out_killing: config.fade_out_killing,
-            drums_only: false,
-        };
+    catch_panic(XSynth_RealtimeSynth::null(), || unsafe {
+        let settings = convert_realtime_config(&config);
 
-        let drumvec =
-            std::slice::from_raw_parts(config.drum_channels, config.drum_channels_count as usize);
+        let device = cpal::default_host()
+            .default_output_device()
+            .unwrap_or_else(|| panic!("Failed to find an audio output device"));
+        let stream_config = find_output_config(&device, config.sample_format);
 
-        let ignore_range = {
-            let low = (config.ignore_range & 255) as u8;
-            let high = (config.ignore_range >> 8) as u8;
-            low..=high
+        let synth = RealtimeSynth::open(settings.realtime_config(), &device, stream_config.clone());
+        let backend = RealtimeBackend::Device {
+            synth,
+            device,
+            stream_config,
         };
+        XSynth_RealtimeSynth::from(backend, settings)
+    })
+}
 
-        let options = XSynthRealtimeConfig {
-            channel_init_options,
-            render_window_ms: config.render_window_ms,
-            channel_count: config.channels,
-            drums_channels: Vec::from(drumvec),
-            use_threadpool: config.use_threadpool,
-            ignore_range,
-        };
+/// Initializes the XSynth Realtime module without opening an audio output
+/// device. Instead of being played automatically, the audio has to be pulled
+/// by the caller using XSynth_Realtime_ReadSamples. This is useful for
+/// testing or when the output is handled by the host application.
+///
+/// The render_window_ms setting of the configuration is not used in this mode.
+///
+/// --Parameters--
+/// - config: The initialization configuration (XSynth_RealtimeConfig struct)
+/// - stream_params: Parameters of the output audio (see XSynth_StreamParams)
+///
+/// --Returns--
+/// This function will return the handle of the created realtime synthesizer,
+/// which can be used with all other XSynth_Realtime_* functions.
+#[no_mangle]
+pub extern "C" fn XSynth_Realtime_CreateHeadless(
+    config: XSynth_RealtimeConfig,
+    stream_params: XSynth_StreamParams,
+) -> XSynth_RealtimeSynth {
+    catch_panic(XSynth_RealtimeSynth::null(), || unsafe {
+        let settings = convert_realtime_config(&config);
 
-        let new = RealtimeSynth::open_with_default_output(options);
-        XSynth_RealtimeSynth::from(new)
-    }
+        let backend = RealtimeBackend::Headless {
+            group: settings
+                .group
+                .build(convert_streamparams_to_rust(stream_params)),
+            ignore_range: settings.ignore_range.clone(),
+        };
+        XSynth_RealtimeSynth::from(backend, settings)
+    })
 }
 
 /// Sends a MIDI event to the specified realtime synth instance.
@@ -118,8 +275,140 @@ pub extern "C" fn XSynth_Realtime_SendEvent(
     event: u16,
     params: u16,
 ) {
-    let ev = convert_event(channel, event, params);
-    handle.as_mut().send_event(ev);
+    catch_panic((), || {
+        let ev = convert_event(channel, event, params);
+        handle.as_mut().send_event(ev);
+    })
+}
+
+/// Schedules a MIDI event to be applied to the specified realtime synth
+/// instance at an exact point of its output, which avoids the timing jitter
+/// of applying events as they arrive.
+///
+/// The clock of the synth counts the audio frames (samples per channel)
+/// rendered since the synth was created, starting at 0, and advances with
+/// every call of XSynth_Realtime_ReadSamples. Events with a timestamp that
+/// has already passed are applied at the start of the next read. Scheduled
+/// events are discarded by XSynth_Realtime_Reset.
+///
+/// This is only supported by synths created using
+/// XSynth_Realtime_CreateHeadless. Synths playing to an audio device have no
+/// clock the timestamp could refer to, so the event is rejected. Use
+/// XSynth_Realtime_SendEvent for them instead.
+///
+/// --Parameters--
+/// - handle: The pointer of the realtime synthesizer instance
+/// - channel: The number of the MIDI channel to send the event to
+///         (MIDI channel 1 is 0)
+/// - event: The type of MIDI event sent (see XSynth_ChannelGroup_SendEvent
+///         for available options)
+/// - params: Parameters for the event
+/// - timestamp_samples: The frame of the output at which the event is applied
+///
+/// --Returns--
+/// True if the event was scheduled, or false if the synth plays to an audio
+/// device. In that case, the reason can be retrieved using
+/// XSynth_GetLastError.
+#[no_mangle]
+pub extern "C" fn XSynth_Realtime_SendEventTimestamped(
+    handle: XSynth_RealtimeSynth,
+    channel: u32,
+    event: u16,
+    params: u16,
+    timestamp_samples: u64,
+) -> bool {
+    catch_panic(false, || {
+        let ev = convert_event(channel, event, params);
+        if handle.as_mut().send_event_at(ev, timestamp_samples) {
+            true
+        } else {
+            set_last_error(
+                "Timestamped events are only supported by headless realtime synthesizers",
+            );
+            false
+        }
+    })
+}
+
+/// Sends a stream of raw MIDI bytes to the specified realtime synth instance,
+/// as received from a MIDI input device or read from a MIDI file track
+/// without delta times. The bytes are parsed into MIDI messages, handling
+/// running status, and each message is applied to the synth.
+///
+/// The parser state is kept between calls, so messages may be split across
+/// multiple calls. Supported messages are note on/off, control change,
+/// program change and pitch bend. Of the SysEx messages, only the standard
+/// reset messages are supported (see XSynth_ChannelGroup_SendSysEx). All
+/// other messages are ignored.
+///
+/// --Parameters--
+/// - handle: The pointer of the realtime synthesizer instance
+/// - bytes: Pointer to the raw MIDI bytes
+/// - len: Number of bytes in the above buffer
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_Realtime_SendRawMidiStream(
+    handle: XSynth_RealtimeSynth,
+    bytes: *const u8,
+    len: u64,
+) {
+    catch_panic((), || unsafe {
+        if bytes.is_null() {
+            return;
+        }
+
+        let bytes = std::slice::from_raw_parts(bytes, len as usize);
+        let instance = handle.as_mut();
+        let mut messages = Vec::new();
+        instance.midi_parser.parse(bytes, |msg| messages.push(msg));
+        for msg in messages {
+            match msg {
+                MidiMessage::Event(event) => instance.send_event(event),
+                MidiMessage::SysEx(data) => {
+                    if is_reset_sysex(&data) {
+                        instance.reset();
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Reads audio samples from the specified realtime synth instance, in the
+/// same way as XSynth_ChannelGroup_ReadSamples.
+///
+/// This only works for realtime synthesizers created with
+/// XSynth_Realtime_CreateHeadless, as otherwise the audio is already being
+/// read by the output device.
+///
+/// --Parameters--
+/// - handle: The pointer of the realtime synthesizer instance
+/// - buffer: Pointer to a mutable buffer to receive the audio samples. Each
+///         item of the buffer should correspond to an audio sample of type
+///         32bit float.
+/// - length: Length of the above buffer, or number of samples to read
+///
+/// --Returns--
+/// The number of samples written to the buffer. If the synthesizer plays to
+/// an audio device, nothing is written, 0 is returned and the reason can be
+/// retrieved using XSynth_GetLastError.
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_Realtime_ReadSamples(
+    handle: XSynth_RealtimeSynth,
+    buffer: *mut f32,
+    length: u64,
+) -> u64 {
+    catch_panic(0, || unsafe {
+        if buffer.is_null() {
+            return 0;
+        }
+
+        let slc = std::slice::from_raw_parts_mut(buffer, length as usize);
+        if !handle.as_mut().read_samples(slc) {
+            set_last_error("Samples can only be read from headless realtime synthesizers");
+            return 0;
+        }
+        length
+    })
 }
 
 /// Returns the audio stream parameters of the specified realtime synth
@@ -135,7 +424,75 @@ pub extern "C" fn XSynth_Realtime_SendEvent(
 pub extern "C" fn XSynth_Realtime_GetStreamParams(
     handle: XSynth_RealtimeSynth,
 ) -> XSynth_StreamParams {
-    convert_streamparams_to_c(&handle.as_ref().stream_params())
+    catch_panic(
+        XSynth_StreamParams {
+            sample_rate: 0,
+            audio_channels: 0,
+        },
+        || convert_streamparams_to_c(&handle.as_ref().stream_params()),
+    )
+}
+
+/// Returns the audio output configuration the specified realtime synth
+/// instance is actually using, as negotiated with the output device. Hosts
+/// should use this instead of assuming the configuration they requested.
+///
+/// --Parameters--
+/// - handle: The pointer of the realtime synthesizer instance
+///
+/// --Returns--
+/// This function returns an XSynth_ActualStreamConfig struct.
+#[no_mangle]
+pub extern "C" fn XSynth_Realtime_GetActualStreamConfig(
+    handle: XSynth_RealtimeSynth,
+) -> XSynth_ActualStreamConfig {
+    catch_panic(Default::default(), || match &handle.as_ref().backend {
+        RealtimeBackend::Device { stream_config, .. } => {
+            let (buffer_size_min, buffer_size_max) = match stream_config.buffer_size() {
+                SupportedBufferSize::Range { min, max } => (*min, *max),
+                SupportedBufferSize::Unknown => (0, 0),
+            };
+
+            XSynth_ActualStreamConfig {
+                sample_rate: stream_config.sample_rate().0,
+                audio_channels: stream_config.channels(),
+                buffer_size_min,
+                buffer_size_max,
+            }
+        }
+        RealtimeBackend::Headless { group, .. } => {
+            let params = group.stream_params();
+
+            XSynth_ActualStreamConfig {
+                sample_rate: params.sample_rate,
+                audio_channels: params.channels.count(),
+                buffer_size_min: 0,
+                buffer_size_max: 0,
+            }
+        }
+    })
+}
+
+/// Returns the name of the audio output device the specified realtime synth
+/// instance is playing to.
+///
+/// --Parameters--
+/// - handle: The pointer of the realtime synthesizer instance
+///
+/// --Returns--
+/// A null terminated string of the device name, which has to be freed using
+/// XSynth_FreeString. Null is returned if the synth was created using
+/// XSynth_Realtime_CreateHeadless, or if the name is not available.
+#[no_mangle]
+pub extern "C" fn XSynth_Realtime_GetDeviceName(handle: XSynth_RealtimeSynth) -> *mut c_char {
+    catch_panic(std::ptr::null_mut(), || match &handle.as_ref().backend {
+        RealtimeBackend::Device { device, .. } => device
+            .name()
+            .ok()
+            .and_then(|name| CString::new(name).ok())
+            .map_or(std::ptr::null_mut(), CString::into_raw),
+        RealtimeBackend::Headless { .. } => std::ptr::null_mut(),
+    })
 }
 
 /// Returns the statistics of the specified realtime synth instance as an
@@ -148,13 +505,195 @@ pub extern "C" fn XSynth_Realtime_GetStreamParams(
 /// This function returns an XSynth_RealtimeStats struct.
 #[no_mangle]
 pub extern "C" fn XSynth_Realtime_GetStats(handle: XSynth_RealtimeSynth) -> XSynth_RealtimeStats {
-    let stats = handle.as_ref().get_stats();
+    catch_panic(Default::default(), || match &handle.as_ref().backend {
+        RealtimeBackend::Device { synth, .. } => {
+            let stats = synth.get_stats();
 
-    XSynth_RealtimeStats {
-        voice_count: stats.voice_count(),
-        buffer: stats.buffer().last_samples_after_read(),
-        render_time: stats.buffer().average_renderer_load(),
-    }
+            XSynth_RealtimeStats {
+                voice_count: stats.voice_count(),
+                buffer: stats.buffer().last_samples_after_read(),
+                render_time: stats.buffer().average_renderer_load(),
+            }
+        }
+        RealtimeBackend::Headless { .. } => XSynth_RealtimeStats {
+            voice_count: handle.as_ref().voice_count(),
+            buffer: 0,
+            render_time: 0.0,
+        },
+    })
+}
+
+/// Reads the statistics of the specified realtime synth instance into an
+/// XSynth_RealtimeStatsV2 struct. Only the fields that fit in the given size
+/// are written, so callers compiled against an older, smaller version of the
+/// struct are supported.
+///
+/// --Parameters--
+/// - handle: The pointer of the realtime synthesizer instance
+/// - out: Pointer to the XSynth_RealtimeStatsV2 struct to be filled
+/// - struct_size: The size of the struct the pointer points to, in bytes.
+///         This should be sizeof(XSynth_RealtimeStatsV2).
+///
+/// --Returns--
+/// The number of bytes written to the struct. Fields past that are left
+/// unchanged.
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_Realtime_GetStatsV2(
+    handle: XSynth_RealtimeSynth,
+    out: *mut XSynth_RealtimeStatsV2,
+    struct_size: u32,
+) -> u32 {
+    catch_panic(0, || unsafe {
+        if out.is_null() {
+            return 0;
+        }
+
+        let instance = handle.as_ref();
+        let stats = match &instance.backend {
+            RealtimeBackend::Device { synth, .. } => {
+                let stats = synth.get_stats();
+                XSynth_RealtimeStatsV2 {
+                    voice_count: stats.voice_count(),
+                    buffer: stats.buffer().last_samples_after_read(),
+                    render_time: stats.buffer().average_renderer_load(),
+                    scheduled_events: instance.scheduled_count() as u64,
+                    rendered_frames: 0,
+                }
+            }
+            RealtimeBackend::Headless { .. } => XSynth_RealtimeStatsV2 {
+                voice_count: instance.voice_count(),
+                scheduled_events: instance.scheduled_count() as u64,
+                rendered_frames: instance.clock,
+                ..Default::default()
+            },
+        };
+
+        let size = std::mem::size_of::<XSynth_RealtimeStatsV2>().min(struct_size as usize);
+        std::ptr::copy_nonoverlapping(
+            &stats as *const XSynth_RealtimeStatsV2 as *const u8,
+            out as *mut u8,
+            size,
+        );
+        size as u32
+    })
+}
+
+/// Registers a callback that is called when the render load of the specified
+/// realtime synth instance rises above the overload threshold (see
+/// XSynth_Realtime_SetOverloadThreshold), which usually means audio dropouts
+/// are imminent. The load is checked every 50ms on a separate monitoring
+/// thread, and the callback is called from that thread, never from the audio
+/// thread. It is only called again once the load has dropped below the
+/// threshold and risen above it again.
+///
+/// This only applies to synths playing to an audio device, as headless
+/// synths do not measure their render load.
+///
+/// --Parameters--
+/// - handle: The pointer of the realtime synthesizer instance
+/// - callback: The function to be called with the render load (see
+///         XSynth_RealtimeStats), the active voice count and the user pointer.
+///         Can be null to remove the current callback.
+/// - user: A pointer that is passed to the callback as is
+#[no_mangle]
+pub extern "C" fn XSynth_Realtime_SetOverloadCallback(
+    handle: XSynth_RealtimeSynth,
+    callback: Option<unsafe extern "C" fn(f64, u64, *mut c_void)>,
+    user: *mut c_void,
+) {
+    catch_panic((), || {
+        let instance = handle.as_mut();
+        // Stops the previous monitoring thread, if any
+        instance.overload = None;
+
+        if let (Some(callback), RealtimeBackend::Device { synth, .. }) =
+            (callback, &instance.backend)
+        {
+            instance.overload = Some(OverloadMonitor::start(
+                callback,
+                user,
+                instance.overload_threshold.clone(),
+                synth.get_stats(),
+            ));
+        }
+    })
+}
+
+/// Sets the render load above which the overload callback of the specified
+/// realtime synth instance is called.
+///
+/// --Parameters--
+/// - handle: The pointer of the realtime synthesizer instance
+/// - threshold: The render load threshold, in the same unit as the
+///         render_time field of XSynth_RealtimeStats (default 0.8)
+#[no_mangle]
+pub extern "C" fn XSynth_Realtime_SetOverloadThreshold(
+    handle: XSynth_RealtimeSynth,
+    threshold: f64,
+) {
+    catch_panic((), || {
+        handle
+            .as_ref()
+            .overload_threshold
+            .store(threshold.to_bits(), Ordering::Relaxed);
+    })
+}
+
+/// Limits the number of voices the specified realtime synth instance plays
+/// at the same time, to prevent dropouts during dense passages. Every voice
+/// counts, including the release tails of released notes. When a new voice
+/// would exceed the limit, the oldest voices are killed to make room,
+/// released ones first.
+///
+/// If the synth was created with fade_out_killing, the killed voices fade out
+/// over a few milliseconds, so the voice count can exceed the limit during
+/// the fade. Otherwise they stop at once.
+///
+/// --Parameters--
+/// - handle: The pointer of the realtime synthesizer instance
+/// - max: The maximum number of voices (0 = no limit, the default)
+#[no_mangle]
+pub extern "C" fn XSynth_Realtime_SetMaxVoices(handle: XSynth_RealtimeSynth, max: u64) {
+    catch_panic((), || {
+        handle.as_ref().voice_limit.set_max(max);
+    })
+}
+
+/// Returns the voice limit of the specified realtime synth instance, as set
+/// using XSynth_Realtime_SetMaxVoices. The synth never plays more voices than
+/// this, apart from voices fading out after being killed when the synth uses
+/// fade_out_killing.
+///
+/// --Parameters--
+/// - handle: The pointer of the realtime synthesizer instance
+///
+/// --Returns--
+/// The maximum number of voices, or 0 if there is no limit.
+#[no_mangle]
+pub extern "C" fn XSynth_Realtime_GetMaxVoices(handle: XSynth_RealtimeSynth) -> u64 {
+    catch_panic(0, || handle.as_ref().voice_limit.max())
+}
+
+/// Sets the output volume of the specified realtime synth instance, meant
+/// for a volume fader controlled by the user. Changes are smoothed over about
+/// 30ms, so that the fader can be moved while the synth plays without clicks.
+/// The volume is kept separate from the soundfont gains and output effects,
+/// so a user's fader and automation of those don't override each other.
+///
+/// Headless synths apply the volume to their output after all other
+/// processing. Synths playing to an audio device render inside the audio
+/// stream, so the volume is applied to each voice before they are mixed
+/// instead.
+///
+/// --Parameters--
+/// - handle: The pointer of the realtime synthesizer instance
+/// - volume: The linear volume (1.0 = unchanged, the default). Negative
+///         values are treated as 0.0.
+#[no_mangle]
+pub extern "C" fn XSynth_Realtime_SetVolume(handle: XSynth_RealtimeSynth, volume: f32) {
+    catch_panic((), || {
+        handle.as_mut().set_volume(volume.max(0.0));
+    })
 }
 
 /// Sets the desired layer limit on the specified realtime synth instance.
@@ -166,9 +705,54 @@ pub extern "C" fn XSynth_Realtime_GetStats(handle: XSynth_RealtimeSynth) -> XSyn
 ///         Where MAX is the maximum value of an unsigned 64bit integer
 #[no_mangle]
 pub extern "C" fn XSynth_Realtime_SetLayerCount(handle: XSynth_RealtimeSynth, layers: u64) {
-    handle.as_mut().send_event(SynthEvent::ChannelConfig(
-        ChannelConfigEvent::SetLayerCount(convert_layer_count(layers)),
-    ));
+    catch_panic((), || {
+        handle.as_mut().set_layer_count(convert_layer_count(layers));
+    })
+}
+
+/// Returns the layer limit of the specified realtime synth instance, as set
+/// using XSynth_Realtime_SetLayerCount.
+///
+/// --Parameters--
+/// - handle: The pointer of the realtime synthesizer instance
+///
+/// --Returns--
+/// The layer limit (0 = no limit). If the limit was never set, 0 is returned
+/// and the default limit of XSynth applies.
+#[no_mangle]
+pub extern "C" fn XSynth_Realtime_GetLayerCount(handle: XSynth_RealtimeSynth) -> u64 {
+    catch_panic(0, || {
+        handle.as_ref().layers.map_or(0, |layers| layers as u64)
+    })
+}
+
+/// Changes the drum channels of the specified realtime synth instance. Only
+/// drum presets can be played on drum channels, and program changes on them
+/// select drum kits.
+///
+/// The synthesizer is rebuilt with the new configuration, so all active notes
+/// are stopped and all control change data is reset. The soundfonts and layer
+/// limit of the synthesizer are kept.
+///
+/// --Parameters--
+/// - handle: The pointer of the realtime synthesizer instance
+/// - channels: Array with the IDs of channels that should only be used for
+///         drums
+/// - count: Length of the above array
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_Realtime_SetDrumChannels(
+    handle: XSynth_RealtimeSynth,
+    channels: *const u32,
+    count: u32,
+) {
+    catch_panic((), || unsafe {
+        let drums = if channels.is_null() {
+            Vec::new()
+        } else {
+            Vec::from(std::slice::from_raw_parts(channels, count as usize))
+        };
+        handle.as_mut().set_drum_channels(drums);
+    })
 }
 
 /// Sets a list of soundfonts to be used in the specified realtime synth
@@ -185,13 +769,11 @@ pub extern "C" fn XSynth_Realtime_SetSoundfonts(
     sf_ids: *const XSynth_Soundfont,
     count: u64,
 ) {
-    unsafe {
+    catch_panic((), || unsafe {
         let ids = std::slice::from_raw_parts(sf_ids, count as usize);
         let sfvec = sfids_to_vec(ids);
-        handle.as_mut().send_event(SynthEvent::ChannelConfig(
-            ChannelConfigEvent::SetSoundfonts(sfvec),
-        ));
-    }
+        handle.as_mut().set_soundfonts(sfvec);
+    })
 }
 
 /// Removes all the soundfonts used in the specified realtime synth instance.
@@ -200,9 +782,9 @@ pub extern "C" fn XSynth_Realtime_SetSoundfonts(
 /// - handle: The handle of the realtime synth instance
 #[no_mangle]
 pub extern "C" fn XSynth_Realtime_ClearSoundfonts(handle: XSynth_RealtimeSynth) {
-    handle.as_mut().send_event(SynthEvent::ChannelConfig(
-        ChannelConfigEvent::SetSoundfonts(Vec::new()),
-    ));
+    catch_panic((), || {
+        handle.as_mut().set_soundfonts(Vec::new());
+    })
 }
 
 /// Resets the specified realtime synth instance. Kills all active notes
@@ -212,7 +794,23 @@ pub extern "C" fn XSynth_Realtime_ClearSoundfonts(handle: XSynth_RealtimeSynth)
 /// - handle: The handle of the realtime synth instance
 #[no_mangle]
 pub extern "C" fn XSynth_Realtime_Reset(handle: XSynth_RealtimeSynth) {
-    handle.as_ref().get_senders().reset_synth();
+    catch_panic((), || {
+        handle.as_mut().reset();
+    })
+}
+
+/// Checks whether the given handle refers to a realtime synth instance that
+/// has been created and not yet dropped. This allows bindings to detect the
+/// use of freed handles instead of crashing.
+///
+/// --Parameters--
+/// - handle: The handle of the realtime synth instance
+///
+/// --Returns--
+/// This function returns true if the handle is valid, or false otherwise.
+#[no_mangle]
+pub extern "C" fn XSynth_Realtime_IsValid(handle: XSynth_RealtimeSynth) -> bool {
+    catch_panic(false, || REALTIME_SYNTHS.contains(handle.synth))
 }
 
 /// Drops the specified realtime synth instance.
@@ -221,5 +819,62 @@ pub extern "C" fn XSynth_Realtime_Reset(handle: XSynth_RealtimeSynth) {
 /// - handle: The handle of the realtime synth instance
 #[no_mangle]
 pub extern "C" fn XSynth_Realtime_Drop(handle: XSynth_RealtimeSynth) {
-    handle.drop();
+    catch_panic((), || {
+        handle.drop();
+    })
+}
+
+/// Returns the sample rates supported by an audio output device. This can be
+/// used to check whether a device supports a sample rate before using it.
+///
+/// Devices usually report ranges of supported sample rates, so for each range
+/// the commonly used sample rates within it are returned, as well as its
+/// limits.
+///
+/// --Parameters--
+/// - device_name: The name of the output device. If null, the default output
+///         device is used.
+/// - out: Pointer to an array to receive the supported sample rates, in
+///         ascending order
+/// - cap: The length of the above array
+///
+/// --Returns--
+/// The number of supported sample rates, which may be larger than cap. If the
+/// device could not be found, 0 is returned.
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_Realtime_GetDeviceSampleRates(
+    device_name: *const c_char,
+    out: *mut u32,
+    cap: u64,
+) -> u64 {
+    catch_panic(0, || unsafe {
+        let Some(device) = find_output_device(device_name) else {
+            return 0;
+        };
+        let Ok(configs) = device.supported_output_configs() else {
+            return 0;
+        };
+
+        let mut rates = Vec::new();
+        for config in configs {
+            let min = config.min_sample_rate().0;
+            let max = config.max_sample_rate().0;
+            rates.push(min);
+            rates.push(max);
+            rates.extend(
+                COMMON_SAMPLE_RATES
+                    .iter()
+                    .filter(|&&rate| rate > min && rate < max),
+            );
+        }
+        rates.sort_unstable();
+        rates.dedup();
+
+        if !out.is_null() {
+            let count = rates.len().min(cap as usize);
+            std::slice::from_raw_parts_mut(out, count).copy_from_slice(&rates[..count]);
+        }
+
+        rates.len() as u64
+    })
 }
@@ -0,0 +1,85 @@
+use std::{
+    ffi::c_void,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// A thread safe list of the addresses of all live handles of one kind.
+pub(crate) struct HandleRegistry {
+    handles: Mutex<Vec<usize>>,
+    /// When false, handles are not tracked and the lock is never taken.
+    enabled: AtomicBool,
+    /// Number of live handles, counted whether or not they are tracked.
+    live: AtomicUsize,
+}
+
+impl HandleRegistry {
+    pub(crate) const fn new() -> Self {
+        Self {
+            handles: Mutex::new(Vec::new()),
+            enabled: AtomicBool::new(true),
+            live: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn insert(&self, ptr: *mut c_void) {
+        self.live.fetch_add(1, Ordering::Relaxed);
+        if self.is_enabled() {
+            self.lock().push(ptr as usize);
+        }
+    }
+
+    pub(crate) fn remove(&self, ptr: *mut c_void) {
+        self.live.fetch_sub(1, Ordering::Relaxed);
+        if self.is_enabled() {
+            self.lock().retain(|&h| h != ptr as usize);
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables tracking of handles. Returns false if any handle
+    /// is alive, since switching would lose track of it or start tracking it
+    /// halfway.
+    pub(crate) fn set_enabled(&self, enabled: bool) -> bool {
+        let _handles = self.lock();
+        if self.is_enabled() == enabled {
+            return true;
+        }
+        if self.live.load(Ordering::Relaxed) > 0 {
+            return false;
+        }
+        self.enabled.store(enabled, Ordering::Relaxed);
+        true
+    }
+
+    pub(crate) fn contains(&self, ptr: *mut c_void) -> bool {
+        !ptr.is_null() && self.lock().contains(&(ptr as usize))
+    }
+
+    /// Calls the given function for every live handle. The registry stays
+    /// locked during the whole iteration, so none of the handles can be
+    /// dropped by another thread in the meantime.
+    pub(crate) fn for_each(&self, mut f: impl FnMut(*mut c_void)) {
+        for &h in self.lock().iter() {
+            f(h as *mut c_void);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<usize>> {
+        self.handles.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// All channel groups that have been created and not yet dropped.
+pub(crate) static GROUPS: HandleRegistry = HandleRegistry::new();
+
+/// All realtime synthesizers that have been created and not yet dropped.
+pub(crate) static REALTIME_SYNTHS: HandleRegistry = HandleRegistry::new();
+
+/// All soundfont handles that have been created and not yet dropped.
+pub(crate) static SOUNDFONTS: HandleRegistry = HandleRegistry::new();
@@ -0,0 +1,121 @@
+use std::f64::consts::PI;
+
+/// Number of input frames on each side of the output position that the sinc
+/// resampler reads.
+const SINC_HALF_WIDTH: usize = 16;
+
+/// The algorithm used to convert audio between sample rates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ResamplerQuality {
+    /// Linear interpolation. Fast, but lets some aliasing through.
+    Linear,
+    /// Blackman windowed sinc interpolation, which also filters out the
+    /// frequencies above the lower of the two rates.
+    Sinc,
+}
+
+impl ResamplerQuality {
+    fn half_width(self) -> usize {
+        match self {
+            ResamplerQuality::Linear => 1,
+            ResamplerQuality::Sinc => SINC_HALF_WIDTH,
+        }
+    }
+}
+
+/// Converts interleaved audio from one sample rate to another. The audio can
+/// be passed in blocks of any size, and the output of consecutive blocks is
+/// continuous.
+pub(crate) struct Resampler {
+    quality: ResamplerQuality,
+    channels: usize,
+    /// Input frames per output frame.
+    step: f64,
+    /// Cutoff frequency of the sinc filter, relative to the input Nyquist
+    /// frequency.
+    cutoff: f64,
+    /// The input frames that are still needed, interleaved.
+    history: Vec<f32>,
+    /// Position of the next output frame, in frames from the start of the
+    /// history.
+    position: f64,
+    weights: Vec<f64>,
+}
+
+impl Resampler {
+    pub(crate) fn new(
+        quality: ResamplerQuality,
+        in_rate: u32,
+        out_rate: u32,
+        channels: usize,
+    ) -> Self {
+        let half = quality.half_width();
+        Self {
+            quality,
+            channels,
+            step: in_rate as f64 / out_rate as f64,
+            cutoff: (out_rate as f64 / in_rate as f64).min(1.0),
+            // The first output frame is centered on the first input frame,
+            // with silence before it.
+            history: vec![0.0; (half - 1) * channels],
+            position: (half - 1) as f64,
+            weights: vec![0.0; half * 2],
+        }
+    }
+
+    /// Resamples the given input and appends the result to the output. The
+    /// last few input frames are kept until enough input follows them.
+    pub(crate) fn process(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        self.history.extend_from_slice(input);
+
+        let half = self.quality.half_width();
+        let frames = self.history.len() / self.channels;
+        loop {
+            let base = self.position.floor() as usize;
+            if base + half >= frames {
+                break;
+            }
+
+            let first = base + 1 - half;
+            let mut total = 0.0;
+            for (i, weight) in self.weights.iter_mut().enumerate() {
+                *weight = kernel(
+                    self.quality,
+                    self.cutoff,
+                    (first + i) as f64 - self.position,
+                );
+                total += *weight;
+            }
+
+            for channel in 0..self.channels {
+                let mut sum = 0.0;
+                for (i, weight) in self.weights.iter().enumerate() {
+                    sum += self.history[(first + i) * self.channels + channel] as f64 * weight;
+                }
+                output.push((sum / total) as f32);
+            }
+            self.position += self.step;
+        }
+
+        let consumed = (self.position.floor() as usize + 1)
+            .saturating_sub(half)
+            .min(frames);
+        self.history.drain(..consumed * self.channels);
+        self.position -= consumed as f64;
+    }
+}
+
+/// Returns the weight of an input frame at the given distance, in frames,
+/// from the output position.
+fn kernel(quality: ResamplerQuality, cutoff: f64, distance: f64) -> f64 {
+    match quality {
+        ResamplerQuality::Linear => (1.0 - distance.abs()).max(0.0),
+        ResamplerQuality::Sinc => {
+            let x = PI * cutoff * distance;
+            let sinc = if x == 0.0 { 1.0 } else { x.sin() / x };
+            let t = distance / SINC_HALF_WIDTH as f64;
+            let window = 0.42 + 0.5 * (PI * t).cos() + 0.08 * (2.0 * PI * t).cos();
+            cutoff * sinc * window
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A lock-free single producer, single consumer ring buffer of audio samples.
+///
+/// Only one thread may push samples and only one thread may pop them at any
+/// given time.
+pub(crate) struct RingBuffer {
+    data: Box<[UnsafeCell<f32>]>,
+    read: AtomicUsize,
+    write: AtomicUsize,
+}
+
+// Each slot is only accessed by either the producer or the consumer, as
+// decided by the read and write positions.
+unsafe impl Sync for RingBuffer {}
+
+impl RingBuffer {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            data: (0..capacity.max(1)).map(|_| UnsafeCell::new(0.0)).collect(),
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of samples that can currently be pushed.
+    pub(crate) fn free(&self) -> usize {
+        let write = self.write.load(Ordering::Relaxed);
+        let read = self.read.load(Ordering::Acquire);
+        self.data.len() - write.wrapping_sub(read)
+    }
+
+    /// Pushes as many of the given samples as fit and returns their count.
+    /// Must only be called by the producer.
+    pub(crate) fn push(&self, samples: &[f32]) -> usize {
+        let write = self.write.load(Ordering::Relaxed);
+        let count = samples.len().min(self.free());
+
+        for (i, &sample) in samples[..count].iter().enumerate() {
+            let slot = &self.data[write.wrapping_add(i) % self.data.len()];
+            unsafe { *slot.get() = sample };
+        }
+
+        self.write
+            .store(write.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Pops samples into the given buffer and returns their count.
+    /// Must only be called by the consumer.
+    pub(crate) fn pop(&self, out: &mut [f32]) -> usize {
+        let read = self.read.load(Ordering::Relaxed);
+        let write = self.write.load(Ordering::Acquire);
+        let count = out.len().min(write.wrapping_sub(read));
+
+        for (i, sample) in out[..count].iter_mut().enumerate() {
+            let slot = &self.data[read.wrapping_add(i) % self.data.len()];
+            *sample = unsafe { *slot.get() };
+        }
+
+        self.read.store(read.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
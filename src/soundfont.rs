@@ -1,16 +1,30 @@
 use std::{
     ffi::{c_char, CStr},
-    path::PathBuf,
-    sync::Arc,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 
 use xsynth_core::soundfont::{Interpolator, SampleSoundfont, SoundfontInitOptions};
 
 use crate::{
-    consts::*, convert_streamparams_to_rust, XSynth_GenDefault_StreamParams, XSynth_Soundfont,
+    consts::*,
+    convert_streamparams_to_rust,
+    error::{catch_panic, set_last_error},
+    handles::SoundfontInstance,
+    registry::{GROUPS, REALTIME_SYNTHS, SOUNDFONTS},
+    XSynth_ChannelGroup, XSynth_GenDefault_StreamParams, XSynth_RealtimeSynth, XSynth_Soundfont,
     XSynth_StreamParams,
 };
 
+/// Counter used to give the generated definitions of WAV instruments unique
+/// file names.
+static NEXT_WAV_INSTRUMENT: AtomicU64 = AtomicU64::new(0);
+
 fn convert_program_value(val: i16) -> Option<u8> {
     if val < 0 {
         None
@@ -19,6 +33,109 @@ fn convert_program_value(val: i16) -> Option<u8> {
     }
 }
 
+pub(crate) fn load_soundfont(
+    path: &Path,
+    options: &XSynth_SoundfontOptions,
+) -> Option<SampleSoundfont> {
+    let sfinit = SoundfontInitOptions {
+        bank: convert_program_value(options.bank),
+        preset: convert_program_value(options.preset),
+        linear_release: options.linear_release,
+        use_effects: options.use_effects,
+        interpolator: match options.interpolator {
+            #[cfg(feature = "interpolation-linear")]
+            INTERPOLATION_LINEAR => Interpolator::Linear,
+            _ => Interpolator::Nearest,
+        },
+    };
+
+    let stream_params = convert_streamparams_to_rust(options.stream_params);
+
+    SampleSoundfont::new(path.to_path_buf(), stream_params, sfinit).ok()
+}
+
+/// Loads a single WAV file as an instrument spanning all keys, through a
+/// minimal SFZ definition written to a new directory in the temporary
+/// directory. The WAV file is linked into the same directory under a fixed
+/// name, or copied if it can't be linked, so that the definition never
+/// contains the original path, whose characters could be misread as SFZ
+/// syntax. The directory is deleted again once the samples are loaded.
+pub(crate) fn load_wav_instrument(
+    wav_path: &Path,
+    root_key: u8,
+    options: &XSynth_SoundfontOptions,
+) -> Result<SampleSoundfont, String> {
+    let dir = std::env::temp_dir().join(format!(
+        "xsynth-{}-{}",
+        std::process::id(),
+        NEXT_WAV_INSTRUMENT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir(&dir).map_err(|e| format!("Error creating instrument directory: {}", e))?;
+
+    let soundfont = write_wav_instrument(&dir, wav_path, root_key).and_then(|path| {
+        load_soundfont(&path, options)
+            .ok_or_else(|| format!("Error loading sample: {:?}", wav_path))
+    });
+    std::fs::remove_dir_all(&dir).ok();
+    soundfont
+}
+
+/// Puts the WAV file and an SFZ definition playing it in the given directory,
+/// and returns the path of the definition.
+fn write_wav_instrument(dir: &Path, wav_path: &Path, root_key: u8) -> Result<PathBuf, String> {
+    let sample = dir.join("sample.wav");
+    if std::fs::hard_link(wav_path, &sample).is_err() {
+        std::fs::copy(wav_path, &sample)
+            .map_err(|e| format!("Error copying sample {:?}: {}", wav_path, e))?;
+    }
+
+    let sfz = format!(
+        "<region>\nsample=sample.wav\nlokey=0\nhikey=127\npitch_keycenter={}\n",
+        root_key.min(127)
+    );
+    let path = dir.join("instrument.sfz");
+    std::fs::write(&path, sfz)
+        .map_err(|e| format!("Error writing instrument definition: {}", e))?;
+    Ok(path)
+}
+
+/// Checks the RIFF structure of an SF2 file, without reading its samples.
+fn validate_sf2(path: &Path) -> Result<(), String> {
+    let mut file = File::open(path).map_err(|e| format!("Could not open file: {}", e))?;
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)
+        .map_err(|_| "File is too short to be a soundfont".to_string())?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"sfbk" {
+        return Err("File is not an SF2 soundfont".to_string());
+    }
+
+    let mut lists = Vec::new();
+    let mut chunk = [0u8; 12];
+    while file.read_exact(&mut chunk).is_ok() {
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as i64;
+        if size < 4 {
+            return Err("Soundfont has a malformed chunk".to_string());
+        }
+        if &chunk[0..4] == b"LIST" {
+            lists.push([chunk[8], chunk[9], chunk[10], chunk[11]]);
+        }
+        // The list type was already read as part of the chunk, and chunks are
+        // padded to an even size.
+        file.seek(SeekFrom::Current(size - 4 + (size & 1)))
+            .map_err(|e| format!("Could not read file: {}", e))?;
+    }
+
+    for list in [b"INFO", b"sdta", b"pdta"] {
+        if !lists.contains(list) {
+            return Err(format!(
+                "Soundfont is missing the {} chunk",
+                String::from_utf8_lossy(list)
+            ));
+        }
+    }
+    Ok(())
+}
+
 /// Options for loading a new XSynth sample soundfont.
 /// - stream_params: Output parameters (see XSynth_StreamParams)
 /// - bank: The bank number (0-128) to extract and use from the soundfont
@@ -31,8 +148,11 @@ fn convert_program_value(val: i16) -> Option<u8> {
 ///         improve performance slightly.
 /// - interpolator: The type of interpolator to use for the new soundfont
 ///         Available values: INTERPOLATION_NEAREST (Nearest Neighbor interpolation),
-///         INTERPOLATION_LINEAR (Linear interpolation)
+///         INTERPOLATION_LINEAR (Linear interpolation). Types not supported by
+///         this build (see XSynth_GetSupportedInterpolators) fall back to
+///         nearest neighbor interpolation.
 #[repr(C)]
+#[derive(Debug, Clone, Copy)]
 pub struct XSynth_SoundfontOptions {
     pub stream_params: XSynth_StreamParams,
     pub bank: i16,
@@ -71,37 +191,338 @@ pub extern "C" fn XSynth_GenDefault_SoundfontOptions() -> XSynth_SoundfontOption
 ///
 /// --Returns--
 /// This function returns the handle of the loaded soundfont, which can be used
-/// to send it to a channel group or realtime synth.
+/// to send it to a channel group or realtime synth. If the soundfont could not
+/// be loaded, a null handle is returned and the reason can be retrieved using
+/// XSynth_GetLastError.
 #[no_mangle]
 pub extern "C" fn XSynth_Soundfont_LoadNew(
     path: *const c_char,
     options: XSynth_SoundfontOptions,
 ) -> XSynth_Soundfont {
-    unsafe {
+    catch_panic(XSynth_Soundfont::null(), || unsafe {
+        if path.is_null() {
+            panic!("Error parsing soundfont path: null pointer");
+        }
+
         let path = PathBuf::from(
             CStr::from_ptr(path)
                 .to_str()
                 .unwrap_or_else(|_| panic!("Error parsing soundfont path: {:?}", path)),
         );
 
-        let sfinit = SoundfontInitOptions {
-            bank: convert_program_value(options.bank),
-            preset: convert_program_value(options.preset),
-            linear_release: options.linear_release,
-            use_effects: options.use_effects,
-            interpolator: match options.interpolator {
-                INTERPOLATION_LINEAR => Interpolator::Linear,
-                _ => Interpolator::Nearest,
-            },
+        let new = load_soundfont(&path, &options)
+            .unwrap_or_else(|| panic!("Error loading soundfont: {:?}", path));
+
+        XSynth_Soundfont::from(Arc::new(SoundfontInstance::new(new, path, options)))
+    })
+}
+
+/// Loads a new XSynth sample soundfont in memory, using a wide (UTF-16) path.
+/// Works like XSynth_Soundfont_LoadNew, but allows loading soundfonts with
+/// non-ASCII characters in their path on Windows.
+///
+/// This function is only available on Windows.
+///
+/// --Parameters--
+/// - path: The null terminated wide string (wchar_t) path of the soundfont
+/// - options: The soundfont initialization options
+///         (XSynth_SoundfontOptions struct)
+///
+/// --Returns--
+/// This function returns the handle of the loaded soundfont, or a null handle
+/// if the soundfont could not be loaded.
+#[cfg(windows)]
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_Soundfont_LoadNewW(
+    path: *const u16,
+    options: XSynth_SoundfontOptions,
+) -> XSynth_Soundfont {
+    use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+
+    catch_panic(XSynth_Soundfont::null(), || unsafe {
+        if path.is_null() {
+            panic!("Error parsing soundfont path: null pointer");
+        }
+
+        let mut len = 0;
+        while *path.add(len) != 0 {
+            len += 1;
+        }
+        let wide = std::slice::from_raw_parts(path, len);
+        let path = PathBuf::from(OsString::from_wide(wide));
+
+        let new = load_soundfont(&path, &options)
+            .unwrap_or_else(|| panic!("Error loading soundfont: {:?}", path));
+
+        XSynth_Soundfont::from(Arc::new(SoundfontInstance::new(new, path, options)))
+    })
+}
+
+/// Loads a single WAV file as an instrument, playable on every key of every
+/// preset and pitched relative to the given root key. This is useful for
+/// quickly testing a sample without building a full soundfont.
+///
+/// Internally, a minimal SFZ definition playing the WAV file is written to
+/// the temporary directory of the system and loaded, so the returned
+/// soundfont works like any other one. The WAV file is linked, or copied if
+/// that is not possible, next to the definition, so any path can be used.
+/// Both are deleted once the sample is loaded, and XSynth_Soundfont_GetPath
+/// returns the path of the WAV file.
+///
+/// --Parameters--
+/// - wav_path: The path of the WAV file to be loaded
+/// - root_key: The key (0-127) at which the sample plays at its original pitch
+/// - options: The soundfont initialization options
+///         (XSynth_SoundfontOptions struct)
+///
+/// --Returns--
+/// This function returns the handle of the loaded soundfont, or a null handle
+/// if it could not be loaded. In that case the reason can be retrieved using
+/// XSynth_GetLastError.
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_Soundfont_LoadWavAsInstrument(
+    wav_path: *const c_char,
+    root_key: u8,
+    options: XSynth_SoundfontOptions,
+) -> XSynth_Soundfont {
+    catch_panic(XSynth_Soundfont::null(), || unsafe {
+        if wav_path.is_null() {
+            set_last_error("Sample path is null");
+            return XSynth_Soundfont::null();
+        }
+
+        let wav_path = PathBuf::from(
+            CStr::from_ptr(wav_path)
+                .to_str()
+                .unwrap_or_else(|_| panic!("Error parsing sample path: {:?}", wav_path)),
+        );
+        let wav_path = wav_path
+            .canonicalize()
+            .unwrap_or_else(|e| panic!("Error opening sample {:?}: {}", wav_path, e));
+
+        let new =
+            load_wav_instrument(&wav_path, root_key, &options).unwrap_or_else(|e| panic!("{}", e));
+
+        let mut instance = SoundfontInstance::new(new, wav_path, options);
+        instance.root_key = Some(root_key);
+        XSynth_Soundfont::from(Arc::new(instance))
+    })
+}
+
+/// Checks whether the given file is a valid soundfont, without loading it.
+/// For SF2 files, the structure of the file is checked without decoding any
+/// samples. For SFZ files, the instrument definition is parsed. This is much
+/// faster than loading the soundfont, but a soundfont that passes the check
+/// may still fail to load if its sample data is corrupt.
+///
+/// --Parameters--
+/// - path: The path of the soundfont to be checked
+///
+/// --Returns--
+/// RESULT_OK if the soundfont is valid, or RESULT_ERROR_SOUNDFONT_LOAD if it
+/// is not. In that case the reason can be retrieved using XSynth_GetLastError.
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_Soundfont_Validate(path: *const c_char) -> i32 {
+    catch_panic(RESULT_ERROR_PANIC, || unsafe {
+        if path.is_null() {
+            set_last_error("Soundfont path is null");
+            return RESULT_ERROR_SOUNDFONT_LOAD;
+        }
+
+        let path = PathBuf::from(
+            CStr::from_ptr(path)
+                .to_str()
+                .unwrap_or_else(|_| panic!("Error parsing soundfont path: {:?}", path)),
+        );
+
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        let result = match extension.as_deref() {
+            Some("sf2") => validate_sf2(&path),
+            Some("sfz") => xsynth_soundfonts::sfz::parse_soundfont(&path)
+                .map(|_| ())
+                .map_err(|e| format!("Error parsing SFZ: {:?}", e)),
+            _ => Err("Unsupported soundfont format".to_string()),
         };
 
-        let stream_params = convert_streamparams_to_rust(options.stream_params);
+        match result {
+            Ok(()) => RESULT_OK,
+            Err(message) => {
+                set_last_error(&message);
+                RESULT_ERROR_SOUNDFONT_LOAD
+            }
+        }
+    })
+}
 
-        let new = SampleSoundfont::new(path.clone(), stream_params, sfinit)
-            .unwrap_or_else(|_| panic!("Error loading soundfont: {:?}", path));
+/// Reloads the desired soundfont from the path it was originally loaded
+/// from, using the same options. All channel groups and realtime synthesizers
+/// using this soundfont will use the new data once the reload is complete.
+///
+/// If the soundfont can no longer be loaded, the previously loaded data is
+/// kept and an error is returned.
+///
+/// --Parameters--
+/// - handle: The handle of the soundfont
+///
+/// --Returns--
+/// RESULT_OK if the soundfont was reloaded successfully, or
+/// RESULT_ERROR_SOUNDFONT_LOAD if it could not be loaded.
+#[no_mangle]
+pub extern "C" fn XSynth_Soundfont_ReloadFromDisk(handle: XSynth_Soundfont) -> i32 {
+    catch_panic(RESULT_ERROR_PANIC, || {
+        if handle.as_ref().reload() {
+            RESULT_OK
+        } else {
+            RESULT_ERROR_SOUNDFONT_LOAD
+        }
+    })
+}
 
-        XSynth_Soundfont::from(Arc::new(new))
-    }
+/// Limits how many zones (samples) of the desired soundfont a single note can
+/// trigger. Heavily layered banks can play many zones per note, which
+/// multiplies the voice count. The zones are kept in the order the soundfont
+/// defines them, so the first ones are usually the primary layers. The limit
+/// applies to the notes played after calling this function.
+///
+/// --Parameters--
+/// - handle: The handle of the soundfont
+/// - max: The maximum number of zones per note (0 = no limit, the default)
+#[no_mangle]
+pub extern "C" fn XSynth_Soundfont_SetMaxZonesPerKey(handle: XSynth_Soundfont, max: u32) {
+    catch_panic((), || {
+        handle.as_ref().max_zones.store(max, Ordering::Relaxed);
+    })
+}
+
+/// Sets the gain of the desired soundfont, which is applied to every voice
+/// it plays before the voices are mixed. This balances the levels of
+/// soundfonts that are mastered too quiet or too loud when layering them. The
+/// gain applies immediately, including to the notes that are already playing,
+/// in every group using the soundfont.
+///
+/// --Parameters--
+/// - handle: The handle of the soundfont
+/// - db: The gain in dB (0.0 = unchanged, the default)
+#[no_mangle]
+pub extern "C" fn XSynth_Soundfont_SetGain(handle: XSynth_Soundfont, db: f32) {
+    catch_panic((), || {
+        let gain = 10f32.powf(db / 20.0);
+        handle
+            .as_ref()
+            .gain
+            .store(gain.to_bits(), Ordering::Relaxed);
+    })
+}
+
+/// Mutes or unmutes a preset of the desired soundfont. A muted preset does
+/// not produce any sound, which helps finding out which instrument of a
+/// soundfont causes a problem without editing it. This applies to the notes
+/// played after calling this function, in every group using the soundfont.
+///
+/// --Parameters--
+/// - handle: The handle of the soundfont
+/// - bank: The bank number of the preset (0-128)
+/// - preset: The preset number (0-127)
+/// - muted: Whether the preset should be muted
+#[no_mangle]
+pub extern "C" fn XSynth_Soundfont_SetPresetMuted(
+    handle: XSynth_Soundfont,
+    bank: u16,
+    preset: u16,
+    muted: bool,
+) {
+    catch_panic((), || {
+        if let (Ok(bank), Ok(preset)) = (u8::try_from(bank), u8::try_from(preset)) {
+            handle.as_ref().set_preset_muted(bank, preset, muted);
+        }
+    })
+}
+
+/// Unmutes all presets of the desired soundfont muted using
+/// XSynth_Soundfont_SetPresetMuted.
+///
+/// --Parameters--
+/// - handle: The handle of the soundfont
+#[no_mangle]
+pub extern "C" fn XSynth_Soundfont_UnmuteAllPresets(handle: XSynth_Soundfont) {
+    catch_panic((), || {
+        handle.as_ref().unmute_all_presets();
+    })
+}
+
+/// Returns the interpolation type used by the desired soundfont.
+///
+/// --Parameters--
+/// - handle: The handle of the soundfont
+///
+/// --Returns--
+/// The interpolator constant: INTERPOLATION_NEAREST (Nearest Neighbor
+/// interpolation) or INTERPOLATION_LINEAR (Linear interpolation)
+#[no_mangle]
+pub extern "C" fn XSynth_Soundfont_GetInterpolator(handle: XSynth_Soundfont) -> u16 {
+    catch_panic(0, || match handle.as_ref().options.interpolator {
+        #[cfg(feature = "interpolation-linear")]
+        INTERPOLATION_LINEAR => INTERPOLATION_LINEAR,
+        _ => INTERPOLATION_NEAREST,
+    })
+}
+
+/// Returns the interpolation types supported by this build of XSynth, so
+/// that only those can be offered to the user.
+///
+/// --Parameters--
+/// - out: Pointer to a mutable buffer to receive the INTERPOLATION_*
+///         constants. Can be null to only query their number.
+/// - cap: Length of the above buffer
+///
+/// --Returns--
+/// The total number of supported interpolation types. If it is larger than
+/// cap, only the first cap of them are written to the buffer.
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_GetSupportedInterpolators(out: *mut u16, cap: u64) -> u64 {
+    catch_panic(0, || unsafe {
+        if !out.is_null() {
+            let count = SUPPORTED_INTERPOLATORS.len().min(cap as usize);
+            std::ptr::copy_nonoverlapping(SUPPORTED_INTERPOLATORS.as_ptr(), out, count);
+        }
+        SUPPORTED_INTERPOLATORS.len() as u64
+    })
+}
+
+/// Returns the path the desired soundfont was loaded from.
+///
+/// --Parameters--
+/// - handle: The handle of the soundfont
+///
+/// --Returns--
+/// A null terminated UTF-8 string of the path. The string belongs to the
+/// soundfont and must not be freed. It stays valid as long as the soundfont
+/// is loaded, so it should be copied if it is needed after the handle is
+/// removed.
+#[no_mangle]
+pub extern "C" fn XSynth_Soundfont_GetPath(handle: XSynth_Soundfont) -> *const c_char {
+    catch_panic(std::ptr::null(), || handle.as_ref().c_path.as_ptr())
+}
+
+/// Checks whether the given handle refers to a soundfont that has been
+/// created and not yet removed. This allows bindings to detect the use of
+/// freed handles instead of crashing. In handle mode (see
+/// XSynth_Config_UseHandleMode), this only checks that the handle is not null.
+///
+/// --Parameters--
+/// - handle: The handle of the soundfont
+///
+/// --Returns--
+/// This function returns true if the handle is valid, or false otherwise.
+#[no_mangle]
+pub extern "C" fn XSynth_Soundfont_IsValid(handle: XSynth_Soundfont) -> bool {
+    catch_panic(false, || {
+        if SOUNDFONTS.is_enabled() {
+            SOUNDFONTS.contains(handle.soundfont)
+        } else {
+            !handle.soundfont.is_null()
+        }
+    })
 }
 
 /// Frees the handle of the desired soundfont.
@@ -115,11 +536,52 @@ pub extern "C" fn XSynth_Soundfont_LoadNew(
 ///
 /// To completely free the memory a soundfont is using you first need
 /// to clear its handle and then remove it from any other places it is
-/// being used.
+/// being used. XSynth_Soundfont_UnloadEverywhere does all of the above
+/// in a single call.
 ///
 /// --Parameters--
 /// - handle: The handle of the soundfont
 #[no_mangle]
 pub extern "C" fn XSynth_Soundfont_Remove(handle: XSynth_Soundfont) {
-    handle.drop();
+    catch_panic((), || {
+        handle.drop();
+    })
+}
+
+/// Removes the desired soundfont from every channel group and realtime
+/// synthesizer it is currently used in, and then frees its handle.
+///
+/// This is a shortcut for clearing the soundfont from each place it was
+/// sent to and then calling XSynth_Soundfont_Remove. If no other handles
+/// point to the same soundfont, its memory will be freed.
+///
+/// Channel groups and realtime synthesizers may be dropped by other threads
+/// during this call, but none of them may be in use by another thread.
+///
+/// --Parameters--
+/// - handle: The handle of the soundfont
+#[no_mangle]
+pub extern "C" fn XSynth_Soundfont_UnloadEverywhere(handle: XSynth_Soundfont) {
+    catch_panic((), || {
+        GROUPS.for_each(|group| {
+            let group = XSynth_ChannelGroup { group };
+            group.as_mut().remove_soundfont(handle.as_ref());
+        });
+
+        REALTIME_SYNTHS.for_each(|synth| {
+            let synth = XSynth_RealtimeSynth { synth };
+            let synth = synth.as_mut();
+            if synth.soundfonts.iter().any(|sf| handle.is_same(sf)) {
+                let remaining = synth
+                    .soundfonts
+                    .iter()
+                    .filter(|sf| !handle.is_same(sf))
+                    .cloned()
+                    .collect();
+                synth.set_soundfonts(remaining);
+            }
+        });
+
+        handle.drop();
+    })
 }
@@ -0,0 +1,173 @@
+use crate::{
+    consts::*, convert_event, convert_layer_count, error::catch_panic, sfids_to_vec,
+    XSynth_ChannelGroup, XSynth_RealtimeSynth, XSynth_Soundfont,
+};
+use std::ffi::c_void;
+
+/// A handle that can refer to either a channel group or a realtime
+/// synthesizer, so that both can be controlled with the same functions.
+/// - kind: The type of the synthesizer the handle refers to
+///         SYNTH_KIND_CHANNEL_GROUP or SYNTH_KIND_REALTIME
+/// - synth: The pointer of the synthesizer instance
+///
+/// The handle does not own the synthesizer, which still has to be dropped
+/// using its own drop function.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct XSynth_SynthHandle {
+    pub kind: u16,
+    pub synth: *mut c_void,
+}
+
+enum Synth {
+    Group(XSynth_ChannelGroup),
+    Realtime(XSynth_RealtimeSynth),
+}
+
+impl XSynth_SynthHandle {
+    fn null() -> Self {
+        Self {
+            kind: SYNTH_KIND_CHANNEL_GROUP,
+            synth: std::ptr::null_mut(),
+        }
+    }
+
+    fn synth(&self) -> Synth {
+        match self.kind {
+            SYNTH_KIND_CHANNEL_GROUP => Synth::Group(XSynth_ChannelGroup { group: self.synth }),
+            SYNTH_KIND_REALTIME => Synth::Realtime(XSynth_RealtimeSynth { synth: self.synth }),
+            _ => panic!("Unknown synth handle kind"),
+        }
+    }
+}
+
+/// Creates a common synth handle referring to the given channel group.
+///
+/// --Parameters--
+/// - handle: The handle of the channel group instance
+///
+/// --Returns--
+/// The common synth handle, which can be used with the XSynth_Synth_*
+/// functions.
+#[no_mangle]
+pub extern "C" fn XSynth_SynthHandle_FromChannelGroup(
+    handle: XSynth_ChannelGroup,
+) -> XSynth_SynthHandle {
+    catch_panic(XSynth_SynthHandle::null(), || XSynth_SynthHandle {
+        kind: SYNTH_KIND_CHANNEL_GROUP,
+        synth: handle.group,
+    })
+}
+
+/// Creates a common synth handle referring to the given realtime synthesizer.
+///
+/// --Parameters--
+/// - handle: The pointer of the realtime synthesizer instance
+///
+/// --Returns--
+/// The common synth handle, which can be used with the XSynth_Synth_*
+/// functions.
+#[no_mangle]
+pub extern "C" fn XSynth_SynthHandle_FromRealtime(
+    handle: XSynth_RealtimeSynth,
+) -> XSynth_SynthHandle {
+    catch_panic(XSynth_SynthHandle::null(), || XSynth_SynthHandle {
+        kind: SYNTH_KIND_REALTIME,
+        synth: handle.synth,
+    })
+}
+
+/// Sends a MIDI event to the desired synthesizer. Works like
+/// XSynth_ChannelGroup_SendEvent and XSynth_Realtime_SendEvent.
+///
+/// --Parameters--
+/// - handle: The common synth handle
+/// - channel: The number of the MIDI channel to send the event to
+///         (MIDI channel 1 is 0)
+/// - event: The type of MIDI event sent (see XSynth_ChannelGroup_SendEvent
+///         for available options)
+/// - params: Parameters for the event
+#[no_mangle]
+pub extern "C" fn XSynth_Synth_SendEvent(
+    handle: XSynth_SynthHandle,
+    channel: u32,
+    event: u16,
+    params: u16,
+) {
+    catch_panic((), || {
+        let ev = convert_event(channel, event, params);
+        match handle.synth() {
+            Synth::Group(group) => group.as_mut().send_event(ev),
+            Synth::Realtime(synth) => synth.as_mut().send_event(ev),
+        }
+    })
+}
+
+/// Sets a list of soundfonts to be used in the desired synthesizer. Works
+/// like XSynth_ChannelGroup_SetSoundfonts and XSynth_Realtime_SetSoundfonts.
+///
+/// --Parameters--
+/// - handle: The common synth handle
+/// - sf_ids: Pointer to an array of soundfont handles
+/// - count: The length of the above array
+#[no_mangle]
+pub unsafe extern "C" fn XSynth_Synth_SetSoundfonts(
+    handle: XSynth_SynthHandle,
+    sf_ids: *const XSynth_Soundfont,
+    count: u64,
+) {
+    catch_panic((), || unsafe {
+        let ids = std::slice::from_raw_parts(sf_ids, count as usize);
+        let sfvec = sfids_to_vec(ids);
+        match handle.synth() {
+            Synth::Group(group) => group.as_mut().set_soundfonts(sfvec),
+            Synth::Realtime(synth) => synth.as_mut().set_soundfonts(sfvec),
+        }
+    })
+}
+
+/// Sets the given layer limit for the desired synthesizer. One layer
+/// corresponds to one voice per key per channel.
+///
+/// --Parameters--
+/// - handle: The common synth handle
+/// - layers: The layer limit (0 = no limit, 1-MAX = limit)
+///         Where MAX is the maximum value of an unsigned 64bit integer
+#[no_mangle]
+pub extern "C" fn XSynth_Synth_SetLayerCount(handle: XSynth_SynthHandle, layers: u64) {
+    catch_panic((), || {
+        let layers = convert_layer_count(layers);
+        match handle.synth() {
+            Synth::Group(group) => group.as_mut().set_layer_count(layers),
+            Synth::Realtime(synth) => synth.as_mut().set_layer_count(layers),
+        }
+    })
+}
+
+/// Returns the active voice count of the desired synthesizer.
+///
+/// --Parameters--
+/// - handle: The common synth handle
+///
+/// --Returns--
+/// A 64bit integer of the voice count
+#[no_mangle]
+pub extern "C" fn XSynth_Synth_VoiceCount(handle: XSynth_SynthHandle) -> u64 {
+    catch_panic(0, || match handle.synth() {
+        Synth::Group(group) => group.as_ref().group.voice_count(),
+        Synth::Realtime(synth) => synth.as_ref().voice_count(),
+    })
+}
+
+/// Resets the desired synthesizer. Kills all active notes and resets all
+/// control change data.
+///
+/// --Parameters--
+/// - handle: The common synth handle
+#[no_mangle]
+pub extern "C" fn XSynth_Synth_Reset(handle: XSynth_SynthHandle) {
+    catch_panic((), || match handle.synth() {
+        Synth::Group(group) => group.as_mut().reset(),
+        Synth::Realtime(synth) => synth.as_mut().reset(),
+    })
+}
@@ -0,0 +1,140 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    path::PathBuf,
+    sync::Mutex,
+};
+use xsynth_core::{
+    soundfont::{SoundfontBase, VoiceSpawner},
+    AudioStreamParams,
+};
+
+/// Writes a short 16bit mono sine wave to a WAV file in the temporary
+/// directory, and returns its path. Each test should use its own name, as
+/// tests run in parallel.
+pub(crate) fn write_test_wav(name: &str) -> PathBuf {
+    let sample_rate = 44100u32;
+    let samples: Vec<i16> = (0..sample_rate / 10)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            ((t * 440.0 * std::f32::consts::TAU).sin() * 16000.0) as i16
+        })
+        .collect();
+    let data_len = samples.len() as u32 * 2;
+
+    let mut wav = Vec::new();
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    let path =
+        std::env::temp_dir().join(format!("xsynth-test-{}-{}.wav", std::process::id(), name));
+    std::fs::write(&path, wav).unwrap();
+    path
+}
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The system allocator, counting the allocations made by the current thread
+/// while inside count_allocations.
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record_allocation();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn record_allocation() {
+    // The thread locals may already be destroyed while a thread exits.
+    if COUNTING.try_with(Cell::get).unwrap_or(false) {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+    }
+}
+
+/// Runs the given function and returns the number of memory allocations it
+/// made on the current thread.
+pub(crate) fn count_allocations(f: impl FnOnce()) -> usize {
+    ALLOCATIONS.with(|count| count.set(0));
+    COUNTING.with(|counting| counting.set(true));
+    f();
+    COUNTING.with(|counting| counting.set(false));
+    ALLOCATIONS.with(Cell::get)
+}
+
+/// A soundfont without any samples, which records the presets that notes
+/// were requested from.
+#[derive(Debug)]
+pub(crate) struct RecordingSoundfont {
+    stream_params: AudioStreamParams,
+    pub requested: Mutex<Vec<(u8, u8)>>,
+}
+
+impl RecordingSoundfont {
+    pub(crate) fn new(stream_params: AudioStreamParams) -> Self {
+        Self {
+            stream_params,
+            requested: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl SoundfontBase for RecordingSoundfont {
+    fn stream_params(&self) -> &AudioStreamParams {
+        &self.stream_params
+    }
+
+    fn get_attack_voice_spawners_at(
+        &self,
+        bank: u8,
+        preset: u8,
+        _key: u8,
+        _vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        self.requested.lock().unwrap().push((bank, preset));
+        Vec::new()
+    }
+
+    fn get_release_voice_spawners_at(
+        &self,
+        _bank: u8,
+        _preset: u8,
+        _key: u8,
+        _vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        Vec::new()
+    }
+}
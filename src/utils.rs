@@ -1,8 +1,7 @@
-use crate::{consts::*, XSynth_Soundfont, XSynth_StreamParams};
+use crate::{consts::*, handles::SoundfontInstance, XSynth_Soundfont, XSynth_StreamParams};
 use std::sync::Arc;
 use xsynth_core::{
     channel::{ChannelAudioEvent, ControlEvent},
-    soundfont::SoundfontBase,
     AudioStreamParams,
 };
 use xsynth_realtime::SynthEvent;
@@ -60,7 +59,46 @@ pub fn convert_event(channel: u32, event: u16, params: u16) -> SynthEvent {
     SynthEvent::Channel(channel, ev)
 }
 
-pub unsafe fn sfids_to_vec(handles: &[XSynth_Soundfont]) -> Vec<Arc<dyn SoundfontBase>> {
+/// Returns a human readable description of an event in the format used by
+/// XSynth_ChannelGroup_SendEvent.
+pub(crate) fn describe_event(channel: u32, event: u16, params: u16) -> String {
+    if event > MIDI_EVENT_COARSETUNE {
+        return format!(
+            "Ch{} Unknown event={} params={}",
+            channel + 1,
+            event,
+            params
+        );
+    }
+
+    let SynthEvent::Channel(_, ev) = convert_event(channel, event, params) else {
+        unreachable!()
+    };
+    let description = match ev {
+        ChannelAudioEvent::NoteOn { key, vel } => format!("NoteOn key={} vel={}", key, vel),
+        ChannelAudioEvent::NoteOff { key } => format!("NoteOff key={}", key),
+        ChannelAudioEvent::AllNotesOff => "AllNotesOff".to_string(),
+        ChannelAudioEvent::AllNotesKilled => "AllNotesKilled".to_string(),
+        ChannelAudioEvent::ResetControl => "ResetControl".to_string(),
+        ChannelAudioEvent::ProgramChange(program) => format!("ProgramChange program={}", program),
+        ChannelAudioEvent::Control(ControlEvent::Raw(controller, value)) => {
+            format!("Control controller={} value={}", controller, value)
+        }
+        ChannelAudioEvent::Control(ControlEvent::PitchBendValue(value)) => {
+            format!("PitchBend value={:.4}", value)
+        }
+        ChannelAudioEvent::Control(ControlEvent::FineTune(cents)) => {
+            format!("FineTune cents={:.2}", cents)
+        }
+        ChannelAudioEvent::Control(ControlEvent::CoarseTune(semitones)) => {
+            format!("CoarseTune semitones={}", semitones)
+        }
+        other => format!("{:?}", other),
+    };
+    format!("Ch{} {}", channel + 1, description)
+}
+
+pub(crate) unsafe fn sfids_to_vec(handles: &[XSynth_Soundfont]) -> Vec<Arc<SoundfontInstance>> {
     handles.iter().map(|handle| handle.clone()).collect()
 }
 
@@ -70,3 +108,137 @@ pub fn convert_layer_count(layers: u64) -> Option<usize> {
         _ => Some(layers as usize),
     }
 }
+
+pub fn remix_channels(input: &[f32], in_channels: u16, output: &mut [f32], out_channels: u16) {
+    match (in_channels, out_channels) {
+        (1, 2) => {
+            for (frame, &sample) in output.chunks_exact_mut(2).zip(input) {
+                frame[0] = sample;
+                frame[1] = sample;
+            }
+        }
+        (2, 1) => {
+            for (out, frame) in output.iter_mut().zip(input.chunks_exact(2)) {
+                *out = (frame[0] + frame[1]) * std::f32::consts::FRAC_1_SQRT_2;
+            }
+        }
+        _ => {
+            let len = output.len().min(input.len());
+            output[..len].copy_from_slice(&input[..len]);
+        }
+    }
+}
+
+/// Returns whether the given SysEx message is a GM System On, GM2 System On,
+/// GS Reset or XG System On message. The leading 0xF0 and trailing 0xF7 bytes
+/// are optional, and any device ID is accepted.
+pub(crate) fn is_reset_sysex(data: &[u8]) -> bool {
+    let data = data.strip_prefix(&[0xF0]).unwrap_or(data);
+    let data = data.strip_suffix(&[0xF7]).unwrap_or(data);
+
+    match data {
+        // GM / GM2 System On
+        [0x7E, _, 0x09, 0x01 | 0x03] => true,
+        // GS Reset
+        [0x41, _, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, _] => true,
+        // XG System On
+        [0x43, dev, 0x4C, 0x00, 0x00, 0x7E, 0x00] => dev & 0xF0 == 0x10,
+        _ => false,
+    }
+}
+
+/// The way samples are stored in a WAV file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum WavEncoding {
+    F32,
+    I16 { big_endian: bool },
+}
+
+impl WavEncoding {
+    fn bytes_per_sample(self) -> u16 {
+        match self {
+            WavEncoding::F32 => 4,
+            WavEncoding::I16 { .. } => 2,
+        }
+    }
+
+    fn big_endian(self) -> bool {
+        matches!(self, WavEncoding::I16 { big_endian: true })
+    }
+
+    /// Returns the size of the sample data in bytes, if it is addressable.
+    pub(crate) fn data_len(self, samples: u64) -> Option<usize> {
+        samples
+            .checked_mul(self.bytes_per_sample() as u64)
+            .and_then(|len| usize::try_from(len).ok())
+    }
+
+    /// Appends a sample to the data of a WAV file.
+    pub(crate) fn write_sample(self, data: &mut Vec<u8>, sample: f32) {
+        match self {
+            WavEncoding::F32 => data.extend_from_slice(&sample.to_le_bytes()),
+            WavEncoding::I16 { big_endian } => {
+                let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                if big_endian {
+                    data.extend_from_slice(&sample.to_be_bytes());
+                } else {
+                    data.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// Returns the header of a WAV file holding the given number of samples, or
+/// None if the sizes do not fit in the header fields. Big-endian files use
+/// the RIFX header, where all header fields are big-endian as well.
+pub(crate) fn wav_header(
+    encoding: WavEncoding,
+    sample_rate: u32,
+    channels: u16,
+    samples: u64,
+) -> Option<Vec<u8>> {
+    let bytes_per_sample = encoding.bytes_per_sample();
+    let data_len = samples
+        .checked_mul(bytes_per_sample as u64)
+        .and_then(|len| u32::try_from(len).ok())?;
+    let riff_len = data_len.checked_add(36)?;
+    let block_align = channels.checked_mul(bytes_per_sample)?;
+    let byte_rate = sample_rate.checked_mul(block_align as u32)?;
+    // PCM or IEEE float format
+    let format: u16 = match encoding {
+        WavEncoding::F32 => 3,
+        WavEncoding::I16 { .. } => 1,
+    };
+
+    let big_endian = encoding.big_endian();
+    let u32_bytes = |v: u32| {
+        if big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        }
+    };
+    let u16_bytes = |v: u16| {
+        if big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        }
+    };
+
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(if big_endian { b"RIFX" } else { b"RIFF" });
+    header.extend_from_slice(&u32_bytes(riff_len));
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&u32_bytes(16));
+    header.extend_from_slice(&u16_bytes(format));
+    header.extend_from_slice(&u16_bytes(channels));
+    header.extend_from_slice(&u32_bytes(sample_rate));
+    header.extend_from_slice(&u32_bytes(byte_rate));
+    header.extend_from_slice(&u16_bytes(block_align));
+    header.extend_from_slice(&u16_bytes(bytes_per_sample * 8));
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&u32_bytes(data_len));
+    Some(header)
+}
@@ -0,0 +1,101 @@
+use crate::channel_state::ChannelState;
+
+/// Rate of the vibrato driven by the mod wheel, in Hz.
+const VIBRATO_RATE_HZ: f64 = 5.0;
+
+/// Depth of the vibrato with the mod wheel fully up, in cents.
+const VIBRATO_DEPTH_CENTS: f32 = 50.0;
+
+/// Number of audio frames rendered between pitch updates of the vibrato.
+pub(crate) const VIBRATO_STEP_FRAMES: usize = 256;
+
+/// A pitch LFO applied to every channel according to its mod wheel (CC1)
+/// position, by offsetting the fine tuning of the channel. The master tuning
+/// of the group is applied the same way.
+pub(crate) struct Vibrato {
+    phase: f64,
+    /// The fine tuning set through events for each channel, in cents.
+    fine_tune: Vec<f32>,
+    /// Offset added to the fine tuning of every channel, in cents.
+    master_tuning: f32,
+    /// Whether the vibrato was applied to each channel on the last step.
+    active: Vec<bool>,
+}
+
+fn mod_wheel(state: &ChannelState) -> u8 {
+    state.controls[1].unwrap_or(0)
+}
+
+impl Vibrato {
+    pub(crate) fn new(channels: usize) -> Self {
+        Self {
+            phase: 0.0,
+            fine_tune: vec![0.0; channels],
+            master_tuning: 0.0,
+            active: vec![false; channels],
+        }
+    }
+
+    /// Returns whether any channel needs its pitch updated while rendering.
+    pub(crate) fn is_needed(&self, states: &[ChannelState]) -> bool {
+        self.active.contains(&true) || states.iter().any(|s| mod_wheel(s) > 0)
+    }
+
+    /// Stores the fine tuning set for a channel, and returns the tuning that
+    /// should actually be applied to it.
+    pub(crate) fn set_fine_tune(&mut self, channel: u32, cents: f32) -> f32 {
+        if let Some(fine_tune) = self.fine_tune.get_mut(channel as usize) {
+            *fine_tune = cents;
+        }
+        cents + self.master_tuning
+    }
+
+    pub(crate) fn master_tuning(&self) -> f32 {
+        self.master_tuning
+    }
+
+    /// Sets the master tuning, and returns the fine tuning every channel
+    /// should have now.
+    pub(crate) fn set_master_tuning(&mut self, cents: f32) -> Vec<(u32, f32)> {
+        self.master_tuning = cents;
+        self.fine_tune
+            .iter()
+            .enumerate()
+            .map(|(channel, base)| (channel as u32, base + cents))
+            .collect()
+    }
+
+    /// Returns the fine tuning each modulated channel should have for the next
+    /// step, and advances the LFO by the given number of frames.
+    pub(crate) fn step(
+        &mut self,
+        states: &[ChannelState],
+        frames: usize,
+        sample_rate: u32,
+    ) -> Vec<(u32, f32)> {
+        let lfo = (self.phase * std::f64::consts::TAU).sin() as f32;
+        let mut tunings = Vec::new();
+
+        for (channel, state) in states.iter().enumerate() {
+            let depth = mod_wheel(state) as f32 / 127.0 * VIBRATO_DEPTH_CENTS;
+            let (Some(active), Some(&base)) =
+                (self.active.get_mut(channel), self.fine_tune.get(channel))
+            else {
+                continue;
+            };
+            let base = base + self.master_tuning;
+
+            if depth > 0.0 {
+                tunings.push((channel as u32, base + depth * lfo));
+                *active = true;
+            } else if *active {
+                tunings.push((channel as u32, base));
+                *active = false;
+            }
+        }
+
+        self.phase += frames as f64 * VIBRATO_RATE_HZ / sample_rate as f64;
+        self.phase = self.phase.fract();
+        tunings
+    }
+}
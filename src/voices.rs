@@ -0,0 +1,397 @@
+use crate::effects::{time_coefficient, GAIN_SMOOTHING_MS};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use xsynth_core::{
+    soundfont::{SoundfontBase, VoiceSpawner},
+    voice::{ReleaseType, Voice, VoiceControlData, VoiceGeneratorBase, VoiceSampleGenerator},
+    AudioStreamParams,
+};
+
+/// The state of a voice spawned under a voice limit, shared between the voice
+/// and the limit.
+#[derive(Debug, Default)]
+struct VoiceState {
+    released: AtomicBool,
+    killed: AtomicBool,
+    ended: AtomicBool,
+}
+
+/// Caps the number of voices of a synth. Every voice spawned by the synth is
+/// registered here, and the oldest voices are killed when a new one would
+/// exceed the limit.
+#[derive(Debug)]
+pub(crate) struct VoiceLimit {
+    /// The maximum number of voices, or 0 for no limit.
+    max: AtomicU64,
+    fade_out_killing: bool,
+    /// The live voices, oldest first.
+    voices: Mutex<VecDeque<Arc<VoiceState>>>,
+}
+
+impl VoiceLimit {
+    pub(crate) fn new(fade_out_killing: bool) -> Arc<Self> {
+        Arc::new(Self {
+            max: AtomicU64::new(0),
+            fade_out_killing,
+            voices: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    pub(crate) fn max(&self) -> u64 {
+        self.max.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_max(&self, max: u64) {
+        self.max.store(max, Ordering::Relaxed);
+    }
+
+    /// Registers a new voice, first killing as many of the existing voices
+    /// as needed for it to fit in the limit. Voices that are already
+    /// released are killed before the ones that are still held.
+    fn register(&self) -> Arc<VoiceState> {
+        let mut voices = self.voices.lock().unwrap_or_else(|e| e.into_inner());
+        voices.retain(|v| !v.ended.load(Ordering::Relaxed) && !v.killed.load(Ordering::Relaxed));
+
+        let max = self.max();
+        if max > 0 {
+            while voices.len() as u64 >= max {
+                let index = voices
+                    .iter()
+                    .position(|v| v.released.load(Ordering::Relaxed))
+                    .unwrap_or(0);
+                if let Some(voice) = voices.remove(index) {
+                    voice.killed.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+
+        let state = Arc::new(VoiceState::default());
+        voices.push_back(state.clone());
+        state
+    }
+}
+
+/// A soundfont whose voices count against a voice limit.
+#[derive(Debug)]
+pub(crate) struct LimitedSoundfont {
+    inner: Arc<dyn SoundfontBase>,
+    limit: Arc<VoiceLimit>,
+}
+
+impl LimitedSoundfont {
+    pub(crate) fn wrap(
+        soundfonts: &[Arc<dyn SoundfontBase>],
+        limit: &Arc<VoiceLimit>,
+    ) -> Vec<Arc<dyn SoundfontBase>> {
+        soundfonts
+            .iter()
+            .map(|sf| {
+                Arc::new(Self {
+                    inner: sf.clone(),
+                    limit: limit.clone(),
+                }) as Arc<dyn SoundfontBase>
+            })
+            .collect()
+    }
+
+    fn limit_spawners(&self, spawners: Vec<Box<dyn VoiceSpawner>>) -> Vec<Box<dyn VoiceSpawner>> {
+        spawners
+            .into_iter()
+            .map(|inner| {
+                Box::new(LimitedSpawner {
+                    inner,
+                    limit: self.limit.clone(),
+                }) as Box<dyn VoiceSpawner>
+            })
+            .collect()
+    }
+}
+
+impl SoundfontBase for LimitedSoundfont {
+    fn stream_params(&self) -> &AudioStreamParams {
+        self.inner.stream_params()
+    }
+
+    fn get_attack_voice_spawners_at(
+        &self,
+        bank: u8,
+        preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        self.limit_spawners(
+            self.inner
+                .get_attack_voice_spawners_at(bank, preset, key, vel),
+        )
+    }
+
+    fn get_release_voice_spawners_at(
+        &self,
+        bank: u8,
+        preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        self.limit_spawners(
+            self.inner
+                .get_release_voice_spawners_at(bank, preset, key, vel),
+        )
+    }
+}
+
+#[derive(Debug)]
+struct LimitedSpawner {
+    inner: Box<dyn VoiceSpawner>,
+    limit: Arc<VoiceLimit>,
+}
+
+impl VoiceSpawner for LimitedSpawner {
+    fn spawn_voice(&self, control: &VoiceControlData) -> Box<dyn Voice> {
+        Box::new(LimitedVoice {
+            inner: self.inner.spawn_voice(control),
+            state: self.limit.register(),
+            fade_out_killing: self.limit.fade_out_killing,
+            kill_sent: false,
+        })
+    }
+}
+
+/// A voice that can be killed by its voice limit. Depending on the
+/// fade_out_killing setting of the synth, a killed voice either fades out
+/// like a voice killed by the layer limit, or ends at once.
+struct LimitedVoice {
+    inner: Box<dyn Voice>,
+    state: Arc<VoiceState>,
+    fade_out_killing: bool,
+    kill_sent: bool,
+}
+
+impl LimitedVoice {
+    fn is_cut(&self) -> bool {
+        !self.fade_out_killing && self.state.killed.load(Ordering::Relaxed)
+    }
+}
+
+impl VoiceGeneratorBase for LimitedVoice {
+    fn ended(&self) -> bool {
+        self.is_cut() || self.inner.ended()
+    }
+
+    fn signal_release(&mut self, rel_type: ReleaseType) {
+        self.state.released.store(true, Ordering::Relaxed);
+        self.inner.signal_release(rel_type);
+    }
+
+    fn process_controls(&mut self, control: &VoiceControlData) {
+        self.inner.process_controls(control);
+    }
+}
+
+impl VoiceSampleGenerator for LimitedVoice {
+    fn render_to(&mut self, buffer: &mut [f32]) {
+        if self.is_cut() {
+            return;
+        }
+        if self.state.killed.load(Ordering::Relaxed) && !self.kill_sent {
+            self.kill_sent = true;
+            self.inner.signal_release(ReleaseType::Kill);
+        }
+        self.inner.render_to(buffer);
+    }
+}
+
+impl Voice for LimitedVoice {
+    fn is_releasing(&self) -> bool {
+        self.inner.is_releasing()
+    }
+
+    fn is_killed(&self) -> bool {
+        self.state.killed.load(Ordering::Relaxed) || self.inner.is_killed()
+    }
+
+    fn velocity(&self) -> u8 {
+        self.inner.velocity()
+    }
+}
+
+impl Drop for LimitedVoice {
+    fn drop(&mut self) {
+        self.state.ended.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A soundfont whose voices are scaled by a smoothed gain, used for the
+/// volume of realtime synths playing to an audio device.
+#[derive(Debug)]
+pub(crate) struct GainSoundfont {
+    inner: Arc<dyn SoundfontBase>,
+    gain: Arc<AtomicU32>,
+}
+
+impl GainSoundfont {
+    pub(crate) fn wrap(
+        soundfonts: &[Arc<dyn SoundfontBase>],
+        gain: &Arc<AtomicU32>,
+    ) -> Vec<Arc<dyn SoundfontBase>> {
+        soundfonts
+            .iter()
+            .map(|sf| {
+                Arc::new(Self {
+                    inner: sf.clone(),
+                    gain: gain.clone(),
+                }) as Arc<dyn SoundfontBase>
+            })
+            .collect()
+    }
+
+    fn smoothing(&self) -> f32 {
+        let params = self.inner.stream_params();
+        let samples_per_sec = params.sample_rate * params.channels.count() as u32;
+        time_coefficient(GAIN_SMOOTHING_MS, samples_per_sec)
+    }
+}
+
+impl SoundfontBase for GainSoundfont {
+    fn stream_params(&self) -> &AudioStreamParams {
+        self.inner.stream_params()
+    }
+
+    fn get_attack_voice_spawners_at(
+        &self,
+        bank: u8,
+        preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        GainSpawner::wrap(
+            self.inner
+                .get_attack_voice_spawners_at(bank, preset, key, vel),
+            &self.gain,
+            self.smoothing(),
+        )
+    }
+
+    fn get_release_voice_spawners_at(
+        &self,
+        bank: u8,
+        preset: u8,
+        key: u8,
+        vel: u8,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        GainSpawner::wrap(
+            self.inner
+                .get_release_voice_spawners_at(bank, preset, key, vel),
+            &self.gain,
+            self.smoothing(),
+        )
+    }
+}
+
+/// Scales the voices it spawns by a gain, which is shared as f32 bits so that
+/// changing it also affects the voices that are already playing.
+#[derive(Debug)]
+pub(crate) struct GainSpawner {
+    inner: Box<dyn VoiceSpawner>,
+    gain: Arc<AtomicU32>,
+    /// Per sample smoothing coefficient of gain changes, or 0.0 to apply
+    /// them at once.
+    smoothing: f32,
+}
+
+impl GainSpawner {
+    pub(crate) fn wrap(
+        spawners: Vec<Box<dyn VoiceSpawner>>,
+        gain: &Arc<AtomicU32>,
+        smoothing: f32,
+    ) -> Vec<Box<dyn VoiceSpawner>> {
+        spawners
+            .into_iter()
+            .map(|inner| {
+                Box::new(Self {
+                    inner,
+                    gain: gain.clone(),
+                    smoothing,
+                }) as Box<dyn VoiceSpawner>
+            })
+            .collect()
+    }
+}
+
+impl VoiceSpawner for GainSpawner {
+    fn spawn_voice(&self, control: &VoiceControlData) -> Box<dyn Voice> {
+        Box::new(GainVoice {
+            inner: self.inner.spawn_voice(control),
+            gain: self.gain.clone(),
+            smoothing: self.smoothing,
+            current: f32::from_bits(self.gain.load(Ordering::Relaxed)),
+            scratch: Vec::new(),
+        })
+    }
+}
+
+struct GainVoice {
+    inner: Box<dyn Voice>,
+    gain: Arc<AtomicU32>,
+    smoothing: f32,
+    /// The gain applied to the last sample, moving towards the shared gain.
+    current: f32,
+    /// The output of the inner voice, which is scaled before being added to
+    /// the buffer.
+    scratch: Vec<f32>,
+}
+
+impl VoiceGeneratorBase for GainVoice {
+    fn ended(&self) -> bool {
+        self.inner.ended()
+    }
+
+    fn signal_release(&mut self, rel_type: ReleaseType) {
+        self.inner.signal_release(rel_type);
+    }
+
+    fn process_controls(&mut self, control: &VoiceControlData) {
+        self.inner.process_controls(control);
+    }
+}
+
+impl VoiceSampleGenerator for GainVoice {
+    fn render_to(&mut self, buffer: &mut [f32]) {
+        let gain = f32::from_bits(self.gain.load(Ordering::Relaxed));
+        if gain == 1.0 && self.current == 1.0 {
+            self.inner.render_to(buffer);
+            return;
+        }
+
+        // Voices add their output to the buffer, so it has to be rendered
+        // separately to scale only this voice.
+        self.scratch.clear();
+        self.scratch.resize(buffer.len(), 0.0);
+        self.inner.render_to(&mut self.scratch);
+        for (out, sample) in buffer.iter_mut().zip(&self.scratch) {
+            self.current = gain + self.smoothing * (self.current - gain);
+            if (self.current - gain).abs() < 1e-5 {
+                self.current = gain;
+            }
+            *out += sample * self.current;
+        }
+    }
+}
+
+impl Voice for GainVoice {
+    fn is_releasing(&self) -> bool {
+        self.inner.is_releasing()
+    }
+
+    fn is_killed(&self) -> bool {
+        self.inner.is_killed()
+    }
+
+    fn velocity(&self) -> u8 {
+        self.inner.velocity()
+    }
+}